@@ -1,5 +1,6 @@
 use criterion::{criterion_group, criterion_main, Criterion, Throughput};
-use quiche_perf::args::{ClientArgs, ServerArgs};
+use quiche_mio_runner::quiche_endpoint::quiche::PROTOCOL_VERSION;
+use quiche_perf::args::{CertGenArgs, ClientArgs, H3Args, Protocol, ServerArgs, SocketArgs};
 use quiche_perf::client::client;
 use quiche_perf::server::server;
 use std::io::Write;
@@ -32,15 +33,28 @@ fn transmit(num_bytes: usize, gso: bool, gro: bool) {
     let (mut close_pipe_tx, mut close_pipe_rx) = mio::unix::pipe::new().unwrap();
     let server_join_handle = thread::spawn(move|| {
         server(&ServerArgs {
-            cert: None,
-            key: None,
+            cert: vec![],
+            key: vec![],
+            cert_gen: CertGenArgs::default(),
+            write_generated_cert: None,
+            client_ca: None,
+            require_client_cert: false,
             max_udp_payload: 1500-44,
             disable_gro: !gro,
             disable_gso: !gso,
+            socket: SocketArgs::default(),
             bind: "127.0.0.1:4433".parse().unwrap(),
             max_streams_bidi: 100,
             max_streams_uni: 100,
             idle_timeout: 1000,
+            root: None,
+            vhost: vec![],
+            connect_udp: false,
+            protocol: Protocol::H3,
+            quic_version: PROTOCOL_VERSION,
+            groups: None,
+            early_data: false,
+            h3: H3Args::default(),
         }, Some(&mut close_pipe_rx));
     });
     let client_join_handle = thread::spawn(move || {
@@ -51,10 +65,37 @@ fn transmit(num_bytes: usize, gso: bool, gro: bool) {
             max_udp_payload: 1500-44,
             disable_gro: !gro,
             disable_gso: !gso,
+            socket: SocketArgs::default(),
             cert: None,
+            pin_spki: vec![],
+            client_cert: None,
+            client_key: None,
             streams: 1,
+            method: "GET".to_string(),
+            headers: vec![],
+            header_size: None,
+            header_count: None,
+            user_agent: "quiche".to_string(),
+            priority: None,
+            verify: false,
+            output: None,
+            output_dir: None,
+            print_headers: false,
+            workload: None,
+            har: None,
+            connect_udp: None,
+            udp_total: 10_000_000,
+            udp_size: 1200,
+            udp_rate: None,
+            compare_direct: false,
+            protocol: Protocol::H3,
+            quic_version: PROTOCOL_VERSION,
+            groups: None,
+            session_file: None,
+            early_data: false,
             silent_close: true,
             idle_timeout: 1000,
+            h3: H3Args::default(),
         });
         assert_eq!(app_data.reqs_complete, 1)
     });
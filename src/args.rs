@@ -1,6 +1,12 @@
 use std::net::SocketAddr;
 use std::path::PathBuf;
-use clap::Args;
+use std::fmt::Display;
+use std::str::FromStr;
+use clap::error::ErrorKind;
+use clap::{Args, Command, ValueEnum};
+use quiche_mio_runner::quiche_endpoint::quiche;
+use crate::mem::{parse_rate, parse_size};
+use crate::priority::Priority;
 
 #[derive(Args)]
 pub struct ClientArgs {
@@ -24,26 +30,138 @@ pub struct ClientArgs {
     /// Disable Generic Send Offload
     #[arg(long)]
     pub disable_gso: bool,
+    #[command(flatten)]
+    pub socket: SocketArgs,
     /// A file path of TLS certificate to trust
     #[arg(long, value_name="PATH")]
     pub cert: Option<PathBuf>,
+    /// Trust the server only if the SHA-256 hash of its leaf certificate's public key matches this base64 value,
+    /// as logged by the server. Replaces the CA verification; may be repeated
+    #[arg(long, value_name="BASE64", conflicts_with="no_verify")]
+    pub pin_spki: Vec<String>,
+    /// Client certificate to present to servers that require one, in PEM format
+    #[arg(long, value_name="PATH", requires="client_key")]
+    pub client_cert: Option<PathBuf>,
+    /// Key of the client certificate, in PEM format
+    #[arg(long, value_name="PATH", requires="client_cert")]
+    pub client_key: Option<PathBuf>,
     /// Number of streams to simultaneously do the same request
     #[arg(long, value_name="STREAMS", default_value_t=1)]
     pub streams: u64,
+    /// Request method
+    #[arg(short='X', long, value_name="METHOD", default_value="GET", value_parser=parse_method)]
+    pub method: String,
+    /// Additional request header in the form `name: value`.
+    /// Replaces the header of the same name the client sends by default,
+    /// e.g. `user-agent` or the pseudo-headers `:authority`, `:scheme` and `:path` derived from the url
+    #[arg(short='H', long="header", value_name="HEADER")]
+    pub headers: Vec<RequestHeader>,
+    /// Add filler request headers of this total size, e.g. `64KB`, to test large header sections
+    #[arg(long, value_name="SIZE", value_parser=parse_size)]
+    pub header_size: Option<usize>,
+    /// Split the filler request headers into this many headers, instead of about 1 KiB each
+    #[arg(long, value_name="COUNT", requires="header_size")]
+    pub header_count: Option<usize>,
+    /// Value of the user-agent header
+    #[arg(long, value_name="USER_AGENT", default_value="quiche")]
+    pub user_agent: String,
+    /// Extensible priority (RFC 9218) of the requests, e.g. `u=1, i`
+    #[arg(long, value_name="PRIORITY")]
+    pub priority: Option<Priority>,
+    /// Verify that the received bodies match the content and length the server generates for the request path,
+    /// e.g. for `/rand/1GB?seed=42`
+    #[arg(long)]
+    pub verify: bool,
+    /// Write the response body to this file, or to stdout if `-`. Requires a single request
+    #[arg(short, long, value_name="PATH")]
+    pub output: Option<PathBuf>,
+    /// Write each response body to a file in this directory, named after the last segment of the request path
+    #[arg(long, value_name="DIR", conflicts_with="output")]
+    pub output_dir: Option<PathBuf>,
+    /// Print the response headers to stdout
+    #[arg(short='I', long)]
+    pub print_headers: bool,
+    /// A file listing the requests to send, instead of requesting the url on every stream.
+    /// Paths are resolved against the url.
+    /// See `Workload` for the file format
+    #[arg(long, value_name="PATH", conflicts_with="streams")]
+    pub workload: Option<PathBuf>,
+    /// A HAR file to replay as a page load.
+    /// Each entry is requested as `/mem/<response body size>` at its start time offset,
+    /// after the entries it depends on completed
+    #[arg(long, value_name="PATH", conflicts_with_all=["streams", "workload"])]
+    pub har: Option<PathBuf>,
+    /// Open a CONNECT-UDP tunnel (RFC 9298) to this UDP target via the server at the url,
+    /// e.g. a `udp-echo` on `127.0.0.1:5000`, and push datagrams through it
+    #[arg(long, value_name="HOST:PORT", conflicts_with_all=["streams", "workload", "har"])]
+    pub connect_udp: Option<String>,
+    /// Total UDP payload to push through the tunnel
+    #[arg(long, value_name="SIZE", value_parser=parse_size, default_value="10MB", requires="connect_udp")]
+    pub udp_total: usize,
+    /// UDP payload per datagram
+    #[arg(long, value_name="SIZE", value_parser=parse_size, default_value="1200", requires="connect_udp")]
+    pub udp_size: usize,
+    /// UDP payload rate, e.g. `100Mbps`, as fast as the congestion control allows if not specified
+    #[arg(long, value_name="RATE", value_parser=parse_rate, requires="connect_udp")]
+    pub udp_rate: Option<u64>,
+    /// Also push the datagrams directly to the UDP target, to compare with the tunnel
+    #[arg(long, requires="connect_udp")]
+    pub compare_direct: bool,
+    /// Application protocol
+    #[arg(long, value_enum, default_value_t=Protocol::H3)]
+    pub protocol: Protocol,
+    /// QUIC version to offer, e.g. `v1` or `0x00000001`.
+    /// A reserved version like `0x1a2a3a4a` forces the server to send a Version Negotiation packet
+    #[arg(long, value_name="VERSION", value_parser=parse_client_quic_version, default_value="v1")]
+    pub quic_version: u32,
+    /// TLS key exchange groups to offer in order of preference, separated by colons,
+    /// e.g. `X25519MLKEM768:X25519:P-256`. BoringSSL's defaults if not specified.
+    /// There is no `--ciphers`: BoringSSL offers all TLS 1.3 cipher suites,
+    /// AES-GCM first with AES hardware support and ChaCha20-Poly1305 first without
+    #[arg(long, value_name="GROUPS")]
+    pub groups: Option<String>,
+    /// Resume the TLS session stored in this file, if it exists,
+    /// and store the session of the last closed connection in it
+    #[arg(long, value_name="PATH")]
+    pub session_file: Option<PathBuf>,
+    /// Send the requests as 0-RTT data when resuming a session with `--session-file`
+    #[arg(long, requires="session_file")]
+    pub early_data: bool,
     #[arg(long, default_value_t=false)]
     pub silent_close: bool,
     #[arg(long, value_name="MS", default_value_t=30_000)]
     pub idle_timeout: u64,
+    #[command(flatten)]
+    pub h3: H3Args,
+}
+
+/// Exit with a usage error of the client, for constraints between the args that clap cannot check
+pub fn client_usage_error(kind: ErrorKind, message: impl Display) -> ! {
+    let mut command = ClientArgs::augment_args(Command::new("client").bin_name("quiche-perf client"));
+    command.error(kind, message).exit()
 }
 
 #[derive(Args)]
 pub struct ServerArgs {
-    /// TLS certificate path. Generated if not specified
+    /// TLS certificate path. Generated if not specified.
+    /// May be repeated, each with its `--key`; the client's SNI selects the first certificate valid for it,
+    /// the first certificate if none is
     #[arg(long, value_name="PATH")]
-    pub cert: Option<PathBuf>,
-    /// TLS certificate key path. Generated if not specified
+    pub cert: Vec<PathBuf>,
+    /// TLS certificate key path, in the order of the certificates. Generated if not specified
+    #[arg(long, value_name="PATH")]
+    pub key: Vec<PathBuf>,
+    #[command(flatten)]
+    pub cert_gen: CertGenArgs,
+    /// Write the generated certificate, key and SPKI hash to `cert.pem`, `key.pem` and `spki` in this directory
+    #[arg(long, value_name="DIR", conflicts_with="cert")]
+    pub write_generated_cert: Option<PathBuf>,
+    /// Request client certificates and verify them with the CA certificates in this PEM file
     #[arg(long, value_name="PATH")]
-    pub key: Option<PathBuf>,
+    pub client_ca: Option<PathBuf>,
+    /// Reject clients without a certificate that verifies with `--client-ca`
+    #[arg(long, requires="client_ca")]
+    pub require_client_cert: bool,
     /// Max UDP payload to send and receive in bytes
     #[arg(long, value_name="BYTES", default_value_t=1500-44)]
     pub max_udp_payload: usize,
@@ -52,6 +170,8 @@ pub struct ServerArgs {
     /// Disable Generic Send Offload
     #[arg(long)]
     pub disable_gso: bool,
+    #[command(flatten)]
+    pub socket: SocketArgs,
     /// Address to bind socket to
     #[arg(long, value_name = "ADDR", default_value = "0.0.0.0:4433")]
     pub bind: SocketAddr,
@@ -63,4 +183,235 @@ pub struct ServerArgs {
     pub max_streams_uni: u64,
     #[arg(long, value_name="MS", default_value_t=30_000)]
     pub idle_timeout: u64,
-}
\ No newline at end of file
+    /// Serve the files below this directory, next to the generated `/mem`, `/pattern` and `/rand` resources
+    #[arg(long, value_name="DIR")]
+    pub root: Option<PathBuf>,
+    /// Serve the files below a directory to requests for a host, e.g. `example.com=www/example`,
+    /// instead of the ones below `--root`; may be repeated
+    #[arg(long, value_name="HOST=DIR")]
+    pub vhost: Vec<VirtualHost>,
+    /// Act as CONNECT-UDP proxy (RFC 9298) to any UDP target
+    #[arg(long)]
+    pub connect_udp: bool,
+    /// Application protocol
+    #[arg(long, value_enum, default_value_t=Protocol::H3)]
+    pub protocol: Protocol,
+    /// QUIC version to accept, e.g. `v1` or `0x00000001`
+    #[arg(long, value_name="VERSION", value_parser=parse_quic_version, default_value="v1")]
+    pub quic_version: u32,
+    /// TLS key exchange groups to accept in order of preference, separated by colons,
+    /// e.g. `X25519MLKEM768:X25519:P-256`. BoringSSL's defaults if not specified.
+    /// There is no `--ciphers`: BoringSSL accepts all TLS 1.3 cipher suites,
+    /// preferring AES-GCM with AES hardware support and ChaCha20-Poly1305 without
+    #[arg(long, value_name="GROUPS")]
+    pub groups: Option<String>,
+    /// Accept 0-RTT data from clients resuming a session
+    #[arg(long)]
+    pub early_data: bool,
+    #[command(flatten)]
+    pub h3: H3Args,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Protocol {
+    /// HTTP/3
+    H3,
+    /// QUIC performance protocol (draft-banks-quic-performance), as spoken by secnetperf and other perf tools.
+    /// The client requests `/mem/<size>` paths as `<size>` bytes and uploads the request body
+    Perf,
+    /// HTTP/0.9 over QUIC, as spoken in the QUIC Interop Runner.
+    /// Requests are `GET <path>` lines without headers, responses are the bare resource
+    #[value(name = "hq-interop")]
+    Hq,
+}
+
+impl Protocol {
+    pub fn application_protos(&self) -> &'static [&'static [u8]] {
+        match self {
+            Protocol::H3 => quiche::h3::APPLICATION_PROTOCOL,
+            Protocol::Perf => crate::perf::APPLICATION_PROTOCOL,
+            Protocol::Hq => crate::hq::APPLICATION_PROTOCOL,
+        }
+    }
+}
+
+/// How to generate a self-signed certificate
+#[derive(Args, Clone, Debug)]
+pub struct CertGenArgs {
+    /// Subject alternative name of the generated certificate, an IP address or DNS name; may be repeated
+    #[arg(long, value_name="SAN", default_values=DEFAULT_CERT_SANS)]
+    pub cert_san: Vec<String>,
+    /// Key type of the generated certificate
+    #[arg(long, value_enum, default_value_t=KeyType::EcdsaP256)]
+    pub key_type: KeyType,
+}
+
+const DEFAULT_CERT_SANS: [&str; 4] = ["quiche", "localhost", "127.0.0.1", "::1"];
+
+impl Default for CertGenArgs {
+    fn default() -> Self {
+        Self {
+            cert_san: DEFAULT_CERT_SANS.map(String::from).to_vec(),
+            key_type: KeyType::EcdsaP256,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum KeyType {
+    EcdsaP256,
+    Ed25519,
+    Rsa2048,
+    Rsa4096,
+}
+
+#[derive(Args)]
+pub struct GenCertArgs {
+    #[command(flatten)]
+    pub cert_gen: CertGenArgs,
+    /// Directory to write `cert.pem`, `key.pem` and `spki` to
+    #[arg(long, value_name="DIR", default_value=".")]
+    pub dir: PathBuf,
+}
+
+#[derive(Args)]
+pub struct UdpEchoArgs {
+    /// Address to bind socket to
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:5000")]
+    pub bind: SocketAddr,
+}
+
+/// HTTP/3 and QPACK settings advertised to the peer, quiche's defaults if not specified
+#[derive(Args, Clone, Debug, Default)]
+pub struct H3Args {
+    /// Max size of a header section the peer may send, in bytes
+    #[arg(long, value_name="BYTES")]
+    pub max_field_section_size: Option<u64>,
+    /// Max capacity of the QPACK dynamic table the peer's encoder may use, in bytes
+    #[arg(long, value_name="BYTES")]
+    pub qpack_max_table_capacity: Option<u64>,
+    /// Max number of streams that may be blocked waiting for QPACK dynamic table updates
+    #[arg(long, value_name="STREAMS")]
+    pub qpack_blocked_streams: Option<u64>,
+    /// Advertise support for the extended CONNECT method, RFC 9220
+    #[arg(long)]
+    pub enable_extended_connect: bool,
+}
+
+/// Options of the UDP socket, the kernel's defaults if not specified
+#[derive(Args, Clone, Debug, Default)]
+pub struct SocketArgs {
+    /// Size of the socket send buffer, e.g. `4MiB`. Clamped by the kernel to `net.core.wmem_max`
+    #[arg(long, value_name="SIZE", value_parser=parse_size)]
+    pub sndbuf: Option<usize>,
+    /// Size of the socket receive buffer, e.g. `4MiB`. Clamped by the kernel to `net.core.rmem_max`
+    #[arg(long, value_name="SIZE", value_parser=parse_size)]
+    pub rcvbuf: Option<usize>,
+    /// DSCP to mark the sent packets with, 0 to 63
+    #[arg(long, value_name="DSCP", value_parser=clap::value_parser!(u8).range(0..64))]
+    pub dscp: Option<u8>,
+}
+
+/// A host with its own root directory, given on the command line
+#[derive(Clone, Debug)]
+pub struct VirtualHost {
+    pub host: String,
+    pub root: PathBuf,
+}
+
+impl FromStr for VirtualHost {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, root) = s.split_once('=').ok_or_else(|| format!("expected `host=dir`: {}", s))?;
+        if host.is_empty() || root.is_empty() {
+            return Err(format!("expected `host=dir`: {}", s));
+        }
+        Ok(Self { host: host.to_ascii_lowercase(), root: PathBuf::from(root) })
+    }
+}
+
+/// A request header given on the command line
+#[derive(Clone, Debug)]
+pub struct RequestHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for RequestHeader {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // pseudo-header names start with a colon
+        let i = s.char_indices()
+            .skip(1)
+            .find(|(_, c)| *c == ':')
+            .map(|(i, _)| i)
+            .ok_or_else(|| format!("expected `name: value`: {}", s))?;
+        let name = s[..i].trim().to_string();
+        let value = s[i + 1..].trim().to_string();
+
+        if let Some(pseudo) = name.strip_prefix(':') {
+            match pseudo {
+                "authority" | "scheme" | "path" => {}
+                "method" => return Err("set the method with -X instead of :method".to_string()),
+                _ => return Err(format!("invalid request pseudo-header: {}", name)),
+            }
+        } else if name.is_empty() || !name.bytes().all(is_header_name_char) {
+            return Err(format!("invalid header name: {:?}", name));
+        } else if name.bytes().any(|c| c.is_ascii_uppercase()) {
+            return Err(format!("header names must be lowercase in HTTP/3: {}", name));
+        } else if matches!(name.as_str(), "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade")
+            || (name == "te" && value != "trailers") {
+            return Err(format!("connection-specific header is not allowed in HTTP/3: {}", name));
+        }
+        if value.bytes().any(|c| c == b'\r' || c == b'\n' || c == 0) {
+            return Err(format!("invalid value of header {}", name));
+        }
+        Ok(Self { name, value })
+    }
+}
+
+/// QUIC version 2, RFC 9369
+const QUIC_V2: u32 = 0x6b33_43cf;
+
+/// Parse a QUIC version supported by quiche, by name or as number
+pub fn parse_quic_version(s: &str) -> Result<u32, String> {
+    let version = match s.to_ascii_lowercase().as_str() {
+        "v1" => quiche::PROTOCOL_VERSION,
+        "v2" => QUIC_V2,
+        v => match v.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => v.parse(),
+        }.map_err(|_| format!("invalid QUIC version, expected v1, v2 or a number: {}", s))?,
+    };
+    if !quiche::version_is_supported(version) {
+        return Err(format!("QUIC version {:#010x} is not supported by quiche", version));
+    }
+    Ok(version)
+}
+
+/// Like [`parse_quic_version`], but also accept the reserved versions of the form `0x?a?a?a?a`
+/// a client can offer to force version negotiation, RFC 9000 section 6.3
+fn parse_client_quic_version(s: &str) -> Result<u32, String> {
+    parse_quic_version(s).or_else(|e| {
+        let version = s.strip_prefix("0x")
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .filter(|v| v & 0x0f0f_0f0f == 0x0a0a_0a0a)
+            .ok_or(e)?;
+        Ok(version)
+    })
+}
+
+/// Parse a request method, a token as defined by RFC 9110
+fn parse_method(s: &str) -> Result<String, String> {
+    if s.is_empty() || !s.bytes().all(is_header_name_char) {
+        return Err(format!("invalid method: {:?}", s));
+    }
+    Ok(s.to_string())
+}
+
+/// token characters, RFC 9110
+fn is_header_name_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+}
@@ -1,30 +1,365 @@
 use std::fs;
-use std::path::PathBuf;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 use boring::base64;
 use boring::pkey::{PKey, Private};
+use boring::rsa::Rsa;
 use boring::sha::sha256;
+use boring::ssl::{select_next_proto, AlpnError, NameType, SniError, SslAlert, SslContext, SslContextBuilder, SslInfoCallbackMode, SslVerifyError, SslVerifyMode};
+use boring::nid::Nid;
 use boring::x509::X509;
-use log::info;
-use rcgen::{generate_simple_self_signed, CertifiedKey};
+use log::{error, info};
+use rcgen::{CertificateParams, DnType, KeyPair, PKCS_ECDSA_P256_SHA256, PKCS_ED25519};
+use crate::args::{CertGenArgs, GenCertArgs, KeyType};
 
-pub fn load_or_generate_keys(cert_path: &Option<PathBuf>, key_path: &Option<PathBuf>) -> (X509, PKey<Private>) {
-    match (&cert_path, &key_path) {
+pub fn load_or_generate_keys(cert_path: Option<&Path>, key_path: Option<&Path>, cert_gen: &CertGenArgs, write_generated: Option<&Path>) -> (X509, PKey<Private>) {
+    match (cert_path, key_path) {
         (Some(cert_path), Some(key_path)) => {
-            let cert = fs::read(cert_path).unwrap();
-            let key = fs::read(key_path).unwrap();
-            let cert = X509::from_pem(&cert).unwrap();
-            let key = PKey::private_key_from_pem(&key).unwrap();
+            let (cert, key) = load_keys(cert_path, key_path);
+            info!("certificate spki: {}", spki_hash(&cert));
             (cert, key)
         },
         (None, None) => {
-            info!("generate self signed TLS certificate");
-            let CertifiedKey { cert, key_pair } = generate_simple_self_signed(["quiche".to_string()].to_vec()).unwrap();
-            let cert = X509::from_pem(cert.pem().as_bytes()).unwrap();
-            let key = PKey::private_key_from_pem(key_pair.serialize_pem().as_bytes()).unwrap();
-            let spki = sha256(&key_pair.public_key_der());
-            info!("certificate spki: {}", base64::encode_block(&spki));
+            info!("generate self signed TLS certificate for {:?}", cert_gen.cert_san);
+            let (cert_pem, key_pem) = generate_cert(cert_gen);
+            let cert = X509::from_pem(cert_pem.as_bytes()).unwrap();
+            let key = PKey::private_key_from_pem(key_pem.as_bytes()).unwrap();
+            info!("certificate spki: {}", spki_hash(&cert));
+            if let Some(dir) = write_generated {
+                write_cert(dir, &cert_pem, &key_pem, &spki_hash(&cert));
+            }
             (cert, key)
         }
         _ => panic!("either provide key and certificate or neither of them")
     }
 }
+
+/// Load a certificate and its key in PEM format
+pub fn load_keys(cert_path: &Path, key_path: &Path) -> (X509, PKey<Private>) {
+    try_load_keys(cert_path, key_path).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Like [`load_keys`], but return an error if the files are invalid or do not match
+pub fn try_load_keys(cert_path: &Path, key_path: &Path) -> Result<(X509, PKey<Private>), String> {
+    let cert = fs::read(cert_path).map_err(|e| format!("failed to read certificate {:?}: {}", cert_path, e))?;
+    let key = fs::read(key_path).map_err(|e| format!("failed to read key {:?}: {}", key_path, e))?;
+    let cert = X509::from_pem(&cert).map_err(|e| format!("invalid certificate {:?}: {}", cert_path, e))?;
+    let key = PKey::private_key_from_pem(&key).map_err(|e| format!("invalid key {:?}: {}", key_path, e))?;
+    if !cert.public_key().is_ok_and(|public_key| public_key.public_eq(&key)) {
+        return Err(format!("key {:?} does not match certificate {:?}", key_path, cert_path));
+    }
+    Ok((cert, key))
+}
+
+/// Generate a self-signed certificate.
+/// Return the certificate and the key in PEM format
+pub fn generate_cert(args: &CertGenArgs) -> (String, String) {
+    let key_pair = match args.key_type {
+        KeyType::EcdsaP256 => KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap(),
+        KeyType::Ed25519 => KeyPair::generate_for(&PKCS_ED25519).unwrap(),
+        // ring can't generate RSA keys
+        KeyType::Rsa2048 | KeyType::Rsa4096 => {
+            let bits = if args.key_type == KeyType::Rsa2048 { 2048 } else { 4096 };
+            let key = PKey::from_rsa(Rsa::generate(bits).unwrap()).unwrap();
+            let pem = String::from_utf8(key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+            KeyPair::from_pem(&pem).unwrap()
+        }
+    };
+    let mut params = CertificateParams::new(args.cert_san.clone())
+        .unwrap_or_else(|e| panic!("invalid subject alternative names {:?}: {}", args.cert_san, e));
+    if let Some(san) = args.cert_san.first() {
+        params.distinguished_name.push(DnType::CommonName, san.as_str());
+    }
+    let cert = params.self_signed(&key_pair).unwrap();
+    (cert.pem(), key_pair.serialize_pem())
+}
+
+/// Write `cert.pem`, `key.pem` and `spki` to `dir`, the key only readable by the owner
+pub fn write_cert(dir: &Path, cert_pem: &str, key_pem: &str, spki: &str) {
+    fs::create_dir_all(dir).unwrap_or_else(|e| panic!("failed to create directory {:?}: {}", dir, e));
+    let write = |name: &str, content: &str, mode: u32| {
+        let path = dir.join(name);
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(mode)
+            .open(&path)
+            // the mode only applies to new files, restrict existing ones before writing to them
+            .and_then(|f| f.set_permissions(fs::Permissions::from_mode(mode)).map(|_| f))
+            .and_then(|mut f| f.write_all(content.as_bytes()))
+            .unwrap_or_else(|e| panic!("failed to write {:?}: {}", path, e));
+    };
+    write("cert.pem", cert_pem, 0o644);
+    write("key.pem", key_pem, 0o600);
+    write("spki", &format!("{}\n", spki), 0o644);
+    info!("wrote cert.pem, key.pem and spki to {:?}", dir);
+}
+
+/// Generate a self-signed certificate and write it to a directory, like the `openssl` commands in the README
+pub fn gen_cert(args: &GenCertArgs) {
+    let (cert_pem, key_pem) = generate_cert(&args.cert_gen);
+    let cert = X509::from_pem(cert_pem.as_bytes()).unwrap();
+    let spki = spki_hash(&cert);
+    write_cert(&args.dir, &cert_pem, &key_pem, &spki);
+    println!("{}", spki);
+}
+
+/// How often the certificate files are checked for changes, at most
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Builds the TLS context of a certificate with its key
+pub type SslContextFactory = Box<dyn Fn(&X509, &PKey<Private>) -> SslContext + Send + Sync>;
+
+/// The certificates of the server.
+/// Each new connection gets the first certificate valid for the client's SNI, the first certificate if none is.
+/// The certificate and key files are reloaded when they change; existing connections keep their certificate
+pub struct CertStore {
+    /// certificate and key file of each certificate, empty if generated
+    files: Vec<(PathBuf, PathBuf)>,
+    build: SslContextFactory,
+    state: Mutex<CertStoreState>,
+}
+
+struct CertStoreState {
+    /// names and TLS context of each certificate
+    contexts: Vec<(Vec<String>, SslContext)>,
+    modified: Vec<Option<SystemTime>>,
+    checked: Instant,
+}
+
+impl CertStore {
+    pub fn new(files: Vec<(PathBuf, PathBuf)>, certs: &[(X509, PKey<Private>)], build: SslContextFactory) -> Self {
+        let contexts = certs.iter().map(|(cert, key)| (cert_names(cert), build(cert, key))).collect();
+        let modified = files.iter().map(modified).collect();
+        Self {
+            files,
+            build,
+            state: Mutex::new(CertStoreState { contexts, modified, checked: Instant::now() }),
+        }
+    }
+
+    /// The TLS context of the certificate for the SNI, after reloading changed files
+    fn select(&self, name: Option<&str>) -> SslContext {
+        let mut state = self.state.lock().unwrap();
+        if !self.files.is_empty() && state.checked.elapsed() >= RELOAD_CHECK_INTERVAL {
+            state.checked = Instant::now();
+            self.reload_if_modified(&mut state);
+        }
+        let name = name.map(str::to_ascii_lowercase);
+        state.contexts.iter()
+            .find(|(names, _)| name.as_ref().is_some_and(|name| names.iter().any(|n| name_matches(n, name))))
+            .unwrap_or(&state.contexts[0])
+            .1
+            .clone()
+    }
+
+    fn reload_if_modified(&self, state: &mut CertStoreState) {
+        let modified = self.files.iter().map(modified).collect::<Vec<_>>();
+        if modified == state.modified {
+            return;
+        }
+        // also if the files are invalid, to not try again until they change
+        state.modified = modified;
+        match self.files.iter().map(|(cert, key)| try_load_keys(cert, key)).collect::<Result<Vec<_>, _>>() {
+            Ok(certs) => {
+                state.contexts = certs.iter()
+                    .map(|(cert, key)| {
+                        info!("reloaded certificate for {:?}, spki: {}", cert_names(cert), spki_hash(cert));
+                        (cert_names(cert), (self.build)(cert, key))
+                    })
+                    .collect();
+            }
+            Err(e) => error!("failed to reload certificates, keep the previous ones: {}", e),
+        }
+    }
+}
+
+/// The later modification time of a certificate and its key file
+fn modified((cert, key): &(PathBuf, PathBuf)) -> Option<SystemTime> {
+    let modified = |path: &PathBuf| fs::metadata(path).and_then(|m| m.modified()).ok();
+    modified(cert).max(modified(key))
+}
+
+/// Switch each new connection to the TLS context of its certificate in the store
+pub fn set_cert_store(b: &mut SslContextBuilder, store: CertStore) {
+    b.set_servername_callback(move |ssl, _| {
+        let ctx = store.select(ssl.servername(NameType::HOST_NAME));
+        ssl.set_ssl_context(&ctx).map_err(|_| SniError::ALERT_FATAL)
+    });
+}
+
+/// Select the application protocol among `protos`, for TLS contexts not built by quiche
+pub fn set_alpn(b: &mut SslContextBuilder, protos: &[&[u8]]) {
+    let wire = protos.iter()
+        .flat_map(|p| std::iter::once(p.len() as u8).chain(p.iter().copied()))
+        .collect::<Vec<u8>>();
+    b.set_alpn_select_callback(move |_, client| select_next_proto(&wire, client).ok_or(AlpnError::ALERT_FATAL));
+}
+
+/// The lowercase DNS names a certificate is valid for, its common name if it has none
+fn cert_names(cert: &X509) -> Vec<String> {
+    let names = cert.subject_alt_names()
+        .map(|sans| sans.iter().filter_map(|n| n.dnsname()).map(str::to_ascii_lowercase).collect::<Vec<_>>())
+        .unwrap_or_default();
+    if !names.is_empty() {
+        return names;
+    }
+    cert.subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .filter_map(|e| e.data().as_utf8().ok().map(|v| v.to_ascii_lowercase()))
+        .collect()
+}
+
+/// Whether a certificate name, possibly with a wildcard for the leftmost label, matches the host name
+fn name_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => name.split_once('.').is_some_and(|(label, rest)| !label.is_empty() && rest == suffix),
+        None => pattern == name,
+    }
+}
+
+/// Base64 of the SHA-256 hash of the certificate's SubjectPublicKeyInfo,
+/// as expected by `--pin-spki` and Chrome's `--ignore-certificate-errors-spki-list`
+pub fn spki_hash(cert: &X509) -> String {
+    let spki = cert.public_key().unwrap().public_key_to_der().unwrap();
+    base64::encode_block(&sha256(&spki))
+}
+
+/// Accept the peer only if the SPKI hash of its leaf certificate is one of `pins`,
+/// instead of verifying the chain. A mismatch aborts the handshake with the alert bad_certificate.
+/// Takes effect once quiche's `verify_peer` is enabled
+pub fn set_spki_pins(b: &mut SslContextBuilder, pins: Vec<String>) {
+    b.set_custom_verify_callback(SslVerifyMode::PEER, move |ssl| {
+        let spki = ssl.peer_certificate().map(|cert| spki_hash(&cert));
+        if spki.as_ref().is_some_and(|spki| pins.contains(spki)) {
+            return Ok(());
+        }
+        error!("certificate spki {} does not match --pin-spki", spki.as_deref().unwrap_or("unknown"));
+        Err(SslVerifyError::Invalid(SslAlert::BAD_CERTIFICATE))
+    });
+}
+
+/// Append the TLS secrets to the file named by the `SSLKEYLOGFILE` environment variable, if set,
+/// e.g. to decrypt packet captures with Wireshark
+pub fn set_keylog_from_env(b: &mut SslContextBuilder) {
+    let Some(path) = std::env::var_os("SSLKEYLOGFILE") else {
+        return
+    };
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .unwrap_or_else(|e| panic!("failed to open key log file {:?}: {}", path, e));
+    info!("log TLS secrets to {:?}", path);
+    let file = Mutex::new(file);
+    b.set_keylog_callback(move |_, line| {
+        writeln!(file.lock().unwrap(), "{}", line).ok();
+    });
+}
+
+/// Restrict the TLS key exchange groups to the given colon separated list, if any
+pub fn set_groups(b: &mut SslContextBuilder, groups: Option<&str>) {
+    if let Some(groups) = groups {
+        b.set_curves_list(groups).unwrap_or_else(|e| panic!("unsupported TLS groups {:?}: {}", groups, e));
+    }
+}
+
+/// Negotiated parameters of a TLS handshake
+#[derive(Clone, Debug, Default)]
+pub struct HandshakeParams {
+    /// cipher suite, e.g. `TLS_AES_128_GCM_SHA256`
+    pub cipher: Option<String>,
+    /// key exchange group, e.g. `X25519`
+    pub group: Option<String>,
+    /// whether the server accepted early data
+    pub early_data: bool,
+}
+
+/// Log the negotiated cipher suite and key exchange group of each handshake.
+/// Return the parameters of the last completed handshake
+pub fn log_handshake_params(b: &mut SslContextBuilder) -> Arc<Mutex<HandshakeParams>> {
+    let params = Arc::new(Mutex::new(HandshakeParams::default()));
+    let last = params.clone();
+    b.set_info_callback(move |ssl, mode, _| {
+        if mode == SslInfoCallbackMode::HANDSHAKE_DONE {
+            let cipher = ssl.current_cipher().map(|c| c.name().to_string());
+            let group = ssl.curve_name().map(str::to_string);
+            let early_data = ssl.early_data_accepted();
+            info!(
+                "TLS handshake done with {} and {}{}",
+                cipher.as_deref().unwrap_or("unknown cipher"),
+                group.as_deref().unwrap_or("unknown group"),
+                if early_data { ", early data accepted" } else { "" },
+            );
+            *last.lock().unwrap() = HandshakeParams { cipher, group, early_data };
+        }
+    });
+    params
+}
+
+/// Request client certificates and verify them with the CA certificates in `ca_path`.
+/// If `require`, clients without a valid certificate are rejected
+pub fn set_client_auth(b: &mut SslContextBuilder, ca_path: &Path, require: bool) {
+    b.set_ca_file(ca_path).unwrap_or_else(|e| panic!("invalid client CA file {:?}: {}", ca_path, e));
+    let mut mode = SslVerifyMode::PEER;
+    if require {
+        mode |= SslVerifyMode::FAIL_IF_NO_PEER_CERT;
+    }
+    b.set_verify(mode);
+}
+
+/// The subject of the certificate, e.g. `CN=client, O=example`
+pub fn subject_to_string(cert: &X509) -> String {
+    cert.subject_name()
+        .entries()
+        .map(|e| format!(
+            "{}={}",
+            e.object().nid().short_name().unwrap_or("?"),
+            e.data().as_utf8().map(|v| v.to_string()).unwrap_or_default(),
+        ))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The TLS alert of a QUIC CRYPTO_ERROR transport error code, RFC 9001 section 4.8
+pub fn tls_alert_description(error_code: u64) -> Option<&'static str> {
+    if !(0x100..=0x1ff).contains(&error_code) {
+        return None;
+    }
+    // RFC 8446 section 6
+    Some(match error_code - 0x100 {
+        0 => "close_notify",
+        10 => "unexpected_message",
+        20 => "bad_record_mac",
+        22 => "record_overflow",
+        40 => "handshake_failure",
+        42 => "bad_certificate",
+        43 => "unsupported_certificate",
+        44 => "certificate_revoked",
+        45 => "certificate_expired",
+        46 => "certificate_unknown",
+        47 => "illegal_parameter",
+        48 => "unknown_ca",
+        49 => "access_denied",
+        50 => "decode_error",
+        51 => "decrypt_error",
+        70 => "protocol_version",
+        71 => "insufficient_security",
+        80 => "internal_error",
+        86 => "inappropriate_fallback",
+        90 => "user_canceled",
+        109 => "missing_extension",
+        110 => "unsupported_extension",
+        112 => "unrecognized_name",
+        113 => "bad_certificate_status_response",
+        115 => "unknown_psk_identity",
+        116 => "certificate_required",
+        120 => "no_application_protocol",
+        _ => "unknown alert",
+    })
+}
@@ -1,56 +1,195 @@
-use crate::args::ClientArgs;
+use crate::args::{client_usage_error, ClientArgs, Protocol};
 use crate::H3_NO_ERROR;
 use log::Level::Info;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use quiche_mio_runner::quiche_endpoint::quiche::h3::NameValue;
-use quiche_mio_runner::quiche_endpoint::quiche::{h3, ConnectionError, PathStats, PROTOCOL_VERSION};
-use quiche_mio_runner::quiche_endpoint::{quiche, Conn};
+use quiche_mio_runner::quiche_endpoint::quiche::{h3, ConnectionError, PathStats};
+use quiche_mio_runner::quiche_endpoint::{quiche, Conn, MAX_UDP_PAYLOAD};
 use quiche_mio_runner::quiche_endpoint::{Endpoint, EndpointConfig, INSTANT_MAX, INSTANT_ZERO};
 use quiche_mio_runner::Socket;
+use boring::ssl::{SslContextBuilder, SslMethod};
+use clap::error::ErrorKind;
 use std::cmp::{max, min};
 use std::fmt::{Debug, Formatter};
-use std::time::Instant;
+use std::fs::File;
+use std::io::{stdout, BufWriter, Write};
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::str::{from_utf8, FromStr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use quiche_mio_runner as runner;
-use crate::h3::hdrs_to_strings;
+use crate::h3::{error_hint, field_section_size, filler_headers, h3_config, hdrs_to_strings, log_peer_settings};
+use crate::cert::{load_keys, log_handshake_params, set_groups, set_keylog_from_env, set_spki_pins, tls_alert_description, HandshakeParams};
+use crate::content::{Content, Verifier};
+use crate::dgram;
+use crate::dgram::{DgramSender, DgramStats};
+use crate::masque::{connect_udp_path, direct_udp_test, parse_http_dgram, parse_udp_dgram, udp_dgram_prefix, CONNECT_UDP_PROTOCOL, DRAIN_TIMEOUT};
+use crate::har::workload_from_har;
+use crate::hq::request_line;
+use crate::mem::MemRequest;
+use crate::priority::Priority;
+use crate::sockopt::{configure_socket, SocketInfo};
+use crate::wakeup::Wakeup;
+use crate::workload::{Workload, WorkloadEntry};
 
 type Runner = runner::Runner<ConnAppData, AppData, ()>;
 
 pub struct AppData {
-    conn_id: usize,
     h3_config: h3::Config,
     silent_close: bool,
+    start_instant: Instant,
+    /// zero bytes to send as request bodies
+    zeros: Vec<u8>,
+    /// for building test datagrams
+    dgram_buf: Vec<u8>,
+    reqs_total: usize,
+    /// indexed by workload entry
+    entries_complete: Vec<bool>,
+    /// report the completion of all requests as page load time
+    page_load: bool,
+    /// where to write the response body to, if any
+    output: Option<Box<dyn Write + Send>>,
+    print_headers: bool,
+    protocol: Protocol,
+    /// effective options of the UDP socket
+    pub socket_info: SocketInfo,
+    /// wakes the runner when requests are due
+    wakeup: Wakeup,
+    /// parameters of the last TLS handshake
+    tls_params: Arc<Mutex<HandshakeParams>>,
+    /// TLS alerts that closed connections, sent or received, e.g. `bad_certificate`
+    pub tls_alerts: Vec<&'static str>,
+    /// where to store the session of closed connections for resumption
+    session_file: Option<PathBuf>,
     pub reqs_complete: usize,
+    /// results of the completed requests, in order of completion
+    pub results: Vec<RequestResult>,
 }
 
 struct ConnAppData {
     h3_conn: Option<h3::Connection>,
     reqs: Vec<PartialRequest>,
-    reqs_hdrs_sent: usize,
     reqs_complete: usize,
+    peer_settings_logged: bool,
+    handshake_logged: bool,
+    /// id of the next stream to open, for the protocols without HTTP/3 framing
+    next_stream_id: u64,
 }
 
-#[derive(Clone)]
 struct PartialRequest {
+    /// index of the workload entry
+    entry: usize,
     hdrs: Vec<h3::Header>,
+    body_size: usize,
+    /// sent before the body if the protocol has no HTTP/3 framing,
+    /// the requested size for the perf protocol or the request line for hq-interop
+    raw_request: Option<Vec<u8>>,
+    start_offset: Duration,
+    depends_on: Option<usize>,
+    priority: Option<Priority>,
+    /// some if the body is verified
+    verify: Option<Verifier>,
     stream_id: Option<u64>,
+    /// including the raw request
+    body_sent: usize,
+    status: Option<u16>,
+    trailers: Vec<(String, String)>,
     received_header_instant: Option<Instant>,
     received_body_instant: Option<Instant>,
     received_body_bytes: usize,
+    /// some once a test datagram of this request arrived
+    dgram: Option<DgramStats>,
+    /// some if this is a CONNECT-UDP request
+    tunnel: Option<Tunnel>,
+    /// where to write the response body to, if written per request
+    output: Option<BufWriter<File>>,
+}
+
+/// Pushes the test datagrams through a CONNECT-UDP tunnel to an echo target
+struct Tunnel {
+    sender: DgramSender,
+    /// bytes on the wire in both directions when the tunnel was established
+    start_wire_bytes: Option<u64>,
+    /// when the last datagram was sent
+    sent_instant: Option<Instant>,
+    /// whether the request stream is closed
+    closed: bool,
+}
+
+/// The result of a completed request
+#[derive(Clone, Debug)]
+pub struct RequestResult {
+    /// index of the workload entry
+    pub entry: usize,
+    pub path: String,
+    /// status code of the response
+    pub status: Option<u16>,
+    /// trailers received after the body
+    pub trailers: Vec<(String, String)>,
+    pub received_header_instant: Instant,
+    pub received_body_instant: Instant,
+    pub received_body_bytes: usize,
+    /// requested priority, if any
+    pub priority: Option<Priority>,
+    /// whether the body was verified
+    pub verified: bool,
+    /// offset of the first body byte that did not match the expected content,
+    /// the end of the body if it is shorter than expected
+    pub first_mismatch: Option<usize>,
+    /// length of the body, if it was verified
+    pub expected_body_bytes: Option<usize>,
+    /// statistics of the received test datagrams, if any
+    pub dgram: Option<DgramStats>,
+    /// QUIC version negotiated on the connection
+    pub quic_version: u32,
+    /// whether the connection resumed a TLS session
+    pub resumed: bool,
+    /// negotiated TLS parameters,
+    /// the same for all connections as they share the TLS config and the server
+    pub tls: HandshakeParams,
 }
 
-#[allow(clippy::field_reassign_with_default)]
 pub fn client(args: &ClientArgs) -> AppData {
+    run_client(args, None)
+}
+
+/// Like [`client`], but send the requests of `workload` instead of the ones given by the args
+pub fn client_with_workload(args: &ClientArgs, workload: Workload) -> AppData {
+    run_client(args, Some(workload))
+}
+
+#[allow(clippy::field_reassign_with_default)]
+fn run_client(args: &ClientArgs, workload: Option<Workload>) -> AppData {
     let socket = Socket::bind("0.0.0.0:0".parse().unwrap(), args.disable_gro, false, args.disable_gso).unwrap();
     assert_eq!(socket.enable_gro, !args.disable_gro);
     assert!(socket.enable_pacing);
     assert_eq!(socket.enable_gso, !args.disable_gso);
     let local_addr = socket.local_addr;
+    let socket_info = configure_socket(&socket.sock, local_addr, &args.socket);
 
+    let tls_params;
     let mut quic_config = {
-        let mut c = quiche::Config::new(PROTOCOL_VERSION).unwrap();
+        let mut c = quiche::Config::with_boring_ssl_ctx_builder(args.quic_version, {
+            let mut b = SslContextBuilder::new(SslMethod::tls()).unwrap();
+            b.set_default_verify_paths().unwrap();
+            set_keylog_from_env(&mut b);
+            set_groups(&mut b, args.groups.as_deref());
+            tls_params = log_handshake_params(&mut b);
+            if !args.pin_spki.is_empty() {
+                set_spki_pins(&mut b, args.pin_spki.clone());
+            }
+            if let (Some(cert), Some(key)) = (&args.client_cert, &args.client_key) {
+                let (cert, key) = load_keys(cert, key);
+                b.set_certificate(&cert).unwrap();
+                b.set_private_key(&key).unwrap();
+            }
+            b
+        }).unwrap();
+        // only sets the verify mode, a pinning callback stays in place
         c.verify_peer(!args.no_verify);
-        c.set_application_protos(quiche::h3::APPLICATION_PROTOCOL).unwrap();
+        c.set_application_protos(args.protocol.application_protos()).unwrap();
         c.set_max_idle_timeout(args.idle_timeout);
         c.set_initial_max_streams_bidi(100);
         c.set_initial_max_streams_uni(100);
@@ -65,15 +204,73 @@ pub fn client(args: &ClientArgs) -> AppData {
         c.set_max_stream_window(16_777_216);
         c.enable_pacing(true);
         c.grease(false);
+        c.enable_dgram(true, dgram::RECV_QUEUE_LEN, dgram::SEND_QUEUE_LEN);
+        if args.early_data {
+            c.enable_early_data();
+        }
         if let Some(cert) = &args.cert {
             c.load_verify_locations_from_file(cert.to_str().expect("Invalid certificate path")).expect("Failed to load certificate");
         }
         c
     };
 
-    let h3_config = h3::Config::new().unwrap();
+    let h3_config = h3_config(&args.h3);
 
     let url = url::Url::parse(&args.url).unwrap();
+    let connect_udp_target = args.connect_udp.as_ref().map(|target| {
+        let (host, port) = target.rsplit_once(':')
+            .and_then(|(host, port)| Some((host.trim_start_matches('[').trim_end_matches(']'), port.parse::<u16>().ok()?)))
+            .unwrap_or_else(|| panic!("invalid UDP target, expected <host>:<port>: {}", target));
+        (host.to_string(), port)
+    });
+
+    let workload = match (workload, &args.workload, &args.har) {
+        (Some(workload), _, _) => workload,
+        (None, Some(path), _) => Workload::from_file(path)
+            .unwrap_or_else(|e| panic!("invalid workload file {:?}: {}", path, e)),
+        (None, _, Some(path)) => workload_from_har(path)
+            .unwrap_or_else(|e| panic!("invalid HAR file {:?}: {}", path, e)),
+        (None, None, None) => Workload {
+            entries: vec![WorkloadEntry {
+                conn: 0,
+                method: match connect_udp_target {
+                    Some(_) => "CONNECT".to_string(),
+                    None => args.method.clone(),
+                },
+                path: match &connect_udp_target {
+                    Some((host, port)) => connect_udp_path(host, *port),
+                    None => url[url::Position::BeforePath..].to_string(),
+                },
+                body_size: 0,
+                start_offset: Duration::ZERO,
+                depends_on: None,
+                priority: args.priority,
+            }; args.streams as usize],
+        },
+    };
+    let output: Option<Box<dyn Write + Send>> = match &args.output {
+        None => None,
+        Some(path) => {
+            if workload.entries.len() > 1 {
+                client_usage_error(ErrorKind::ArgumentConflict, "--output requires a single request, use --output-dir for several");
+            }
+            if path.as_os_str() == "-" {
+                Some(Box::new(BufWriter::new(stdout())))
+            } else {
+                let file = File::create(path)
+                    .unwrap_or_else(|e| panic!("failed to create output file {:?}: {}", path, e));
+                Some(Box::new(BufWriter::new(file)))
+            }
+        }
+    };
+    if let Some(dir) = &args.output_dir {
+        std::fs::create_dir_all(dir)
+            .unwrap_or_else(|e| panic!("failed to create output directory {:?}: {}", dir, e));
+    }
+    let authority = match url.port() {
+        None => url.host_str().unwrap().to_string(),
+        Some(port) => format!("{}:{}", url.host_str().unwrap(), port)
+    };
 
     let peer_addr = match args.addr {
         Some(v) => v,
@@ -91,42 +288,142 @@ pub fn client(args: &ClientArgs) -> AppData {
             c
         },
         AppData {
-            conn_id: 0,
             h3_config,
             silent_close: args.silent_close,
+            start_instant: Instant::now(),
+            zeros: vec![0; 65536],
+            dgram_buf: vec![0; MAX_UDP_PAYLOAD],
+            reqs_total: workload.entries.len(),
+            entries_complete: vec![false; workload.entries.len()],
+            page_load: args.har.is_some(),
+            output,
+            print_headers: args.print_headers,
+            protocol: args.protocol,
+            socket_info,
+            wakeup: Wakeup::new(local_addr),
+            tls_params,
+            tls_alerts: vec![],
+            session_file: args.session_file.clone(),
             reqs_complete: 0,
+            results: Vec::with_capacity(workload.entries.len()),
         },
     );
 
-    endpoint.connect(
-        url.domain(),
-        local_addr,
-        peer_addr,
-        &mut quic_config,
-        ConnAppData {
-            h3_conn: None,
-            reqs: vec![PartialRequest {
-                hdrs: vec![
-                    h3::Header::new(b":method", b"GET"),
-                    h3::Header::new(b":scheme", b"https"),
-                    h3::Header::new(b":authority", match url.port() {
-                        None => url.host_str().unwrap().to_string(),
-                        Some(port) => format!("{}:{}", url.host_str().unwrap(), port)
-                    }.as_bytes()),
-                    h3::Header::new(b":path", url[url::Position::BeforePath..].as_bytes()),
-                    h3::Header::new(b"user-agent", b"quiche"),
-                ],
+    for conn_index in 0..workload.num_conns() {
+        let reqs = workload.entries.iter()
+            .enumerate()
+            .filter(|(_, e)| e.conn == conn_index)
+            .map(|(i, e)| PartialRequest {
+                entry: i,
+                hdrs: {
+                    let mut hdrs = vec![
+                        h3::Header::new(b":method", e.method.as_bytes()),
+                        h3::Header::new(b":scheme", b"https"),
+                        h3::Header::new(b":authority", authority.as_bytes()),
+                        h3::Header::new(b":path", e.path.as_bytes()),
+                        h3::Header::new(b"user-agent", args.user_agent.as_bytes()),
+                    ];
+                    if let Some(p) = e.priority {
+                        hdrs.push(h3::Header::new(b"priority", p.to_string().as_bytes()));
+                    }
+                    if connect_udp_target.is_some() {
+                        hdrs.push(h3::Header::new(b":protocol", CONNECT_UDP_PROTOCOL.as_bytes()));
+                        hdrs.push(h3::Header::new(b"capsule-protocol", b"?1"));
+                    }
+                    // user headers replace the default ones of the same name, but not each other
+                    let defaults = hdrs.len();
+                    for h in &args.headers {
+                        let header = h3::Header::new(h.name.as_bytes(), h.value.as_bytes());
+                        match hdrs[..defaults].iter_mut().find(|v| v.name().eq_ignore_ascii_case(h.name.as_bytes())) {
+                            Some(v) => *v = header,
+                            None => hdrs.push(header),
+                        }
+                    }
+                    if let Some(size) = args.header_size {
+                        hdrs.extend(filler_headers(size, args.header_count));
+                    }
+                    hdrs
+                },
+                body_size: e.body_size,
+                raw_request: match args.protocol {
+                    Protocol::H3 => None,
+                    Protocol::Perf => Some(perf_request(&e.path)),
+                    Protocol::Hq => {
+                        if e.body_size > 0 {
+                            client_usage_error(ErrorKind::ArgumentConflict, format!("hq-interop requests have no body: {}", e.path));
+                        }
+                        Some(request_line(&e.path))
+                    }
+                },
+                start_offset: e.start_offset,
+                depends_on: e.depends_on,
+                priority: e.priority,
+                verify: if args.verify {
+                    let verifier = MemRequest::from_str(&e.path).ok().map(|r| Verifier::new(r.content, r.size));
+                    if verifier.is_none() {
+                        warn!("cannot verify {}, content is unknown", e.path);
+                    }
+                    verifier
+                } else {
+                    None
+                },
                 stream_id: None,
+                body_sent: 0,
+                status: None,
+                trailers: vec![],
                 received_header_instant: None,
                 received_body_instant: None,
                 received_body_bytes: 0,
-            }; args.streams as usize],
-            reqs_hdrs_sent: 0,
-            reqs_complete: 0,
-        },
-        None,
-        None,
-    );
+                dgram: None,
+                tunnel: connect_udp_target.as_ref().map(|_| Tunnel {
+                    sender: DgramSender::new(args.udp_total, Some(args.udp_size), args.udp_rate),
+                    start_wire_bytes: None,
+                    sent_instant: None,
+                    closed: false,
+                }),
+                output: args.output_dir.as_ref().map(|dir| {
+                    let path = output_file_path(dir, &e.path);
+                    let file = File::create(&path)
+                        .unwrap_or_else(|e| panic!("failed to create output file {:?}: {}", path, e));
+                    BufWriter::new(file)
+                }),
+            })
+            .collect::<Vec<_>>();
+        if reqs.is_empty() {
+            continue; // gap in connection indices
+        }
+        endpoint.connect(
+            url.domain(),
+            local_addr,
+            peer_addr,
+            &mut quic_config,
+            ConnAppData {
+                h3_conn: None,
+                reqs,
+                reqs_complete: 0,
+                peer_settings_logged: false,
+                handshake_logged: false,
+                next_stream_id: 0,
+            },
+            None,
+            None,
+        );
+    }
+
+    let session = args.session_file.as_ref().and_then(|path| match std::fs::read(path) {
+        Ok(session) => Some(session),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => panic!("failed to read session file {:?}: {}", path, e),
+    });
+    if let Some(session) = &session {
+        // before the runner sends the first packets
+        for i in endpoint.conn_index_iter() {
+            if let (Some(conn), _) = endpoint.conn_with_app_data_mut(i)
+                && let Err(e) = conn.conn.set_session(session) {
+                warn!("{} failed to resume session: {:?}", conn.conn.trace_id(), e);
+            }
+        }
+    }
 
     let mut runner = Runner::new(
         {
@@ -142,31 +439,85 @@ pub fn client(args: &ClientArgs) -> AppData {
     runner.register_socket(socket);
 
     runner.run();
-    runner.endpoint.take_app_data()
+    let app_data = runner.endpoint.take_app_data();
+    if args.compare_direct {
+        let (host, port) = connect_udp_target.unwrap();
+        let target = (host.as_str(), port).to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .unwrap_or_else(|| panic!("failed to resolve {}:{}", host, port));
+        let direct = direct_udp_test(target, args.udp_total, args.udp_size, args.udp_rate);
+        print_dgram_stats("direct", &direct);
+    }
+    app_data
 }
 
 fn post_handle_recvs(runner: &mut Runner) {
     let endpoint = &mut runner.endpoint;
-    let (conn, app_data) = endpoint.conn_with_app_data_mut(endpoint.app_data().conn_id);
-    let conn = conn.unwrap();
-    if !conn.conn.is_established() && !conn.conn.is_in_early_data() {
-        return; // not ready for h3 yet
-    }
-    if conn.app_data.h3_conn.is_none() {
-        conn.app_data.h3_conn = Some(h3::Connection::with_transport(
-            &mut conn.conn,
-            &app_data.h3_config,
-        ).expect("Unable to create HTTP/3 connection, check the server's uni stream limit and window size"));
-    }
-    let closed = handle_h3_responses(conn, &mut runner.buf, app_data);
-    if closed && app_data.silent_close {
-        endpoint.remove_conn(endpoint.app_data().conn_id);
+    for i in endpoint.conn_index_iter() {
+        let (conn, app_data) = endpoint.conn_with_app_data_mut(i);
+        let Some(conn) = conn else {
+            continue
+        };
+        if !conn.app_data.handshake_logged && conn.conn.is_established() {
+            conn.app_data.handshake_logged = true;
+            log_handshake(&conn.conn, app_data.start_instant);
+        }
+        if conn.conn.local_error().is_some() {
+            continue; // closing
+        }
+        if !conn.conn.is_established() && !conn.conn.is_in_early_data() {
+            continue; // not ready for h3 yet
+        }
+        if app_data.protocol != Protocol::H3 {
+            let closed = handle_stream_responses(conn, &mut runner.buf, app_data);
+            if closed && app_data.silent_close {
+                endpoint.remove_conn(i);
+            }
+            continue;
+        }
+        if conn.app_data.h3_conn.is_none() {
+            conn.app_data.h3_conn = Some(h3::Connection::with_transport(
+                &mut conn.conn,
+                &app_data.h3_config,
+            ).expect("Unable to create HTTP/3 connection, check the server's uni stream limit and window size"));
+        }
+        let closed = handle_h3_responses(conn, &mut runner.buf, app_data);
+        if !conn.app_data.peer_settings_logged {
+            let h3_conn = conn.app_data.h3_conn.as_ref().unwrap();
+            conn.app_data.peer_settings_logged = log_peer_settings(conn.conn.trace_id(), h3_conn);
+        }
+        if closed && app_data.silent_close {
+            endpoint.remove_conn(i);
+        }
     }
 
     send_requests(endpoint);
 }
 
-fn on_close(c: &Conn<ConnAppData>, _: &mut AppData) {
+/// Log the negotiated QUIC version and the handshake duration
+fn log_handshake(quic_conn: &quiche::Connection, start_instant: Instant) {
+    info!(
+        "{} handshake complete with QUIC version {:#010x} after {:.3} ms{}",
+        quic_conn.trace_id(),
+        quic_conn.version(),
+        start_instant.elapsed().as_secs_f64() * 1E3,
+        if quic_conn.is_resumed() { ", resumed" } else { "" },
+    );
+}
+
+fn on_close(c: &Conn<ConnAppData>, app_data: &mut AppData) {
+    if let Some(path) = &app_data.session_file
+        && let Some(session) = c.conn.session() {
+        std::fs::write(path, session)
+            .unwrap_or_else(|e| panic!("failed to write session file {:?}: {}", path, e));
+    }
+    for err in [c.conn.peer_error(), c.conn.local_error()].into_iter().flatten() {
+        if !err.is_app
+            && let Some(alert) = tls_alert_description(err.error_code) {
+            app_data.tls_alerts.push(alert);
+        }
+    }
     if let Some(err) = c.conn.peer_error() {
         error!(
             "{} peer connection error: {:?}",
@@ -194,13 +545,16 @@ fn on_close(c: &Conn<ConnAppData>, _: &mut AppData) {
 
 /// return true if connection closed
 fn handle_h3_responses(conn: &mut Conn<ConnAppData>, buf: &mut [u8], app_data: &mut AppData) -> bool {
-    let h3_conn = conn.app_data.h3_conn.as_mut().unwrap();
+    // datagrams first, they were sent before the trailers that complete the request
+    handle_dgrams(conn, buf, app_data.start_instant);
     loop {
+        let h3_conn = conn.app_data.h3_conn.as_mut().unwrap();
         match h3_conn.poll(&mut conn.conn) {
             Ok((stream_id, h3::Event::Headers { list, .. })) => {
                 info!(
-                    "recv h3 resp hdr {:?} on stream id {}",
+                    "recv h3 resp hdr {:?} ({} B header section) on stream id {}",
                     hdrs_to_strings(&list),
+                    field_section_size(&list),
                     stream_id
                 );
                 let req = conn.app_data
@@ -208,22 +562,37 @@ fn handle_h3_responses(conn: &mut Conn<ConnAppData>, buf: &mut [u8], app_data: &
                     .iter_mut()
                     .find(|r| r.stream_id == Some(stream_id))
                     .unwrap();
-                req.received_header_instant = Some(Instant::now());
+                if req.received_header_instant.is_some() {
+                    // a second header section after the body
+                    req.trailers = hdrs_to_strings(&list);
+                } else {
+                    req.received_header_instant = Some(Instant::now());
+                    req.status = list.iter()
+                        .find(|h| h.name() == b":status")
+                        .and_then(|h| from_utf8(h.value()).ok()?.parse().ok());
+                }
+                if app_data.print_headers {
+                    let mut stdout = stdout().lock();
+                    for h in &list {
+                        writeln!(stdout, "{}: {}", String::from_utf8_lossy(h.name()), String::from_utf8_lossy(h.value())).unwrap();
+                    }
+                    writeln!(stdout).unwrap();
+                }
             }
             Ok((stream_id, h3::Event::Data)) => {
                 'data: loop {
                     match h3_conn.recv_body(&mut conn.conn, stream_id, buf) {
                         Ok(read) => {
                             debug!(
-                                "got {} bytes of response data on stream {}: {}",
-                                read, stream_id, String::from_utf8_lossy(&buf[..read])
+                                "got {} bytes of response data on stream {}",
+                                read, stream_id
                             );
                             let req = conn.app_data
                                 .reqs
                                 .iter_mut()
                                 .find(|r| r.stream_id == Some(stream_id))
                                 .unwrap();
-                            req.received_body_bytes += read;
+                            receive_body(&mut app_data.output, req, stream_id, &buf[..read]);
                         }
                         Err(h3::Error::Done) => {
                             break 'data;
@@ -235,33 +604,14 @@ fn handle_h3_responses(conn: &mut Conn<ConnAppData>, buf: &mut [u8], app_data: &
                 }
             }
             Ok((stream_id, h3::Event::Finished)) => {
-                let req = conn.app_data
-                    .reqs
-                    .iter_mut()
-                    .find(|r| r.stream_id == Some(stream_id))
-                    .unwrap();
-                req.received_body_instant = Some(Instant::now());
-                conn.app_data.reqs_complete += 1;
-                app_data.reqs_complete += 1;
-                if log::log_enabled!(Info) {
-                    let duration = (req.received_body_instant.unwrap() - req.received_header_instant.unwrap()).as_secs_f64();
-                    let goodput = req.received_body_bytes as f64 * 8f64 / duration;
-                    info!(
-                        "recv h3 resp body {}: {} B, {:.6} s, {:.6} Gbps",
-                        String::from_utf8_lossy(req.hdrs.iter().find(|h| h.name() == b":path").unwrap().value()),
-                        req.received_body_bytes,
-                        duration,
-                        goodput / 1E9,
-                    );
-                }
-                if conn.app_data.reqs_complete == conn.app_data.reqs.len() {
-                    print_total_results(&conn.app_data.reqs);
-                    conn.conn.close(true, H3_NO_ERROR, b"").unwrap();
+                if complete_request(conn, stream_id, app_data) {
                     return true
                 }
             }
-            Ok((_stream_id, h3::Event::Reset(_e))) => {
-                unimplemented!()
+            Ok((stream_id, h3::Event::Reset(e))) => {
+                error!("{} stream {} reset by peer with error code {}", conn.conn.trace_id(), stream_id, e);
+                conn.conn.close(true, H3_NO_ERROR, b"").ok();
+                return true
             }
             Ok((_stream_id, h3::Event::PriorityUpdate)) => {
                 unimplemented!()
@@ -272,69 +622,460 @@ fn handle_h3_responses(conn: &mut Conn<ConnAppData>, buf: &mut [u8], app_data: &
             Err(h3::Error::Done) => {
                 break; // no more events to process
             }
-            Err(_e) => {
-                unimplemented!()
+            Err(e) => {
+                error!("{} HTTP/3 error {:?}{}", conn.conn.trace_id(), e, error_hint(&e));
+                conn.conn.close(true, h3::WireErrorCode::InternalError as u64, b"").ok();
+                return true
             }
         }
     }
     false
 }
 
-fn print_total_results(reqs: &[PartialRequest]) {
+/// Record the result of the request on the stream.
+/// Return true if the connection is closed, because all its requests are complete
+fn complete_request(conn: &mut Conn<ConnAppData>, stream_id: u64, app_data: &mut AppData) -> bool {
+    let req = conn.app_data
+        .reqs
+        .iter_mut()
+        .find(|r| r.stream_id == Some(stream_id))
+        .unwrap();
+    req.received_body_instant = Some(Instant::now());
+    if let Some(sent) = req.trailers.iter().find(|(name, _)| name == "dgram-sent") {
+        let stats = req.dgram.get_or_insert_with(|| DgramStats::new(Instant::now()));
+        stats.sent = sent.1.parse().ok();
+    }
+    if let Some(tunnel) = &req.tunnel
+        && tunnel.sender.seq > 0 {
+        let stats = req.dgram.get_or_insert_with(|| DgramStats::new(Instant::now()));
+        stats.sent = Some(tunnel.sender.seq);
+        // both directions are on the wire, since the tunnel was established
+        let payload = tunnel.sender.sent + stats.received_bytes;
+        let quic_stats = conn.conn.stats();
+        let wire = quic_stats.sent_bytes + quic_stats.recv_bytes - tunnel.start_wire_bytes.unwrap_or(0);
+        info!(
+            "tunnel overhead: {} B on the wire for {} B of UDP payload, {:.1}%",
+            wire,
+            payload,
+            (wire as f64 / payload as f64 - 1.0) * 100.0,
+        );
+    }
+    if let Some(output) = &mut app_data.output {
+        output.flush().expect("failed to write output");
+    }
+    if let Some(output) = &mut req.output {
+        output.flush().expect("failed to write output");
+    }
+    let first_mismatch = req.verify.as_mut().and_then(Verifier::finish);
+    if let Some(verifier) = &req.verify
+        && req.received_body_bytes != verifier.expected_len() {
+        error!("body length mismatch on stream {}: {} B instead of {} B", stream_id, req.received_body_bytes, verifier.expected_len());
+    }
+    conn.app_data.reqs_complete += 1;
+    app_data.reqs_complete += 1;
+    let result = RequestResult {
+        entry: req.entry,
+        path: String::from_utf8_lossy(req.hdrs.iter().find(|h| h.name() == b":path").unwrap().value()).to_string(),
+        status: req.status,
+        trailers: req.trailers.clone(),
+        received_header_instant: req.received_header_instant.unwrap(),
+        received_body_instant: req.received_body_instant.unwrap(),
+        received_body_bytes: req.received_body_bytes,
+        priority: req.priority,
+        verified: req.verify.is_some(),
+        first_mismatch,
+        expected_body_bytes: req.verify.map(|v| v.expected_len()),
+        dgram: req.dgram.clone(),
+        quic_version: conn.conn.version(),
+        resumed: conn.conn.is_resumed(),
+        tls: app_data.tls_params.lock().unwrap().clone(),
+    };
+    if log::log_enabled!(Info) {
+        let duration = (result.received_body_instant - result.received_header_instant).as_secs_f64();
+        let goodput = result.received_body_bytes as f64 * 8f64 / duration;
+        info!(
+            "recv resp body entry {} {}: {} B, {:.6} s, {:.6} Gbps",
+            result.entry,
+            result.path,
+            result.received_body_bytes,
+            duration,
+            goodput / 1E9,
+        );
+    }
+    if let Some(stats) = &result.dgram {
+        print_dgram_stats(&format!("entry {} {}", result.entry, result.path), stats);
+    }
+    app_data.entries_complete[result.entry] = true;
+    app_data.results.push(result);
+    if app_data.reqs_complete == app_data.reqs_total {
+        print_total_results(&app_data.results, app_data.start_instant, app_data.page_load, &app_data.socket_info);
+    }
+    if conn.app_data.reqs_complete == conn.app_data.reqs.len() {
+        conn.conn.close(true, H3_NO_ERROR, b"").unwrap();
+        return true
+    }
+    false
+}
+
+/// Receive the responses of the protocols without HTTP/3 framing,
+/// the bare response body on each stream.
+/// Return true if the connection is closed
+fn handle_stream_responses(conn: &mut Conn<ConnAppData>, buf: &mut [u8], app_data: &mut AppData) -> bool {
+    for stream_id in conn.conn.readable().collect::<Vec<u64>>() {
+        loop {
+            match conn.conn.stream_recv(stream_id, buf) {
+                Ok((read, fin)) => {
+                    debug!("got {} bytes of response data on stream {}", read, stream_id);
+                    let req = conn.app_data
+                        .reqs
+                        .iter_mut()
+                        .find(|r| r.stream_id == Some(stream_id))
+                        .unwrap();
+                    // there are no headers, take the first byte instead
+                    req.received_header_instant.get_or_insert_with(Instant::now);
+                    receive_body(&mut app_data.output, req, stream_id, &buf[..read]);
+                    if fin {
+                        if complete_request(conn, stream_id, app_data) {
+                            return true
+                        }
+                        break
+                    }
+                }
+                Err(quiche::Error::Done) => break,
+                Err(quiche::Error::StreamReset(e)) => {
+                    error!("{} stream {} reset by peer with error code {}", conn.conn.trace_id(), stream_id, e);
+                    conn.conn.close(true, 0, b"").ok();
+                    return true
+                }
+                Err(e) => {
+                    error!("{} failed to receive on stream {}: {:?}", conn.conn.trace_id(), stream_id, e);
+                    break
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Account, verify and write a chunk of the response body
+fn receive_body(output: &mut Option<Box<dyn Write + Send>>, req: &mut PartialRequest, stream_id: u64, data: &[u8]) {
+    write_output(output, req, data);
+    if let Some(offset) = req.verify.as_mut().and_then(|v| v.update(data)) {
+        error!("body mismatch on stream {} at offset {}", stream_id, offset);
+    }
+    req.received_body_bytes += data.len();
+}
+
+/// Write a chunk of the response body to the output of all requests or to the one of this request
+fn write_output(output: &mut Option<Box<dyn Write + Send>>, req: &mut PartialRequest, data: &[u8]) {
+    if let Some(output) = output {
+        output.write_all(data).expect("failed to write output");
+    }
+    if let Some(output) = &mut req.output {
+        output.write_all(data).expect("failed to write output");
+    }
+}
+
+/// The perf request of a `/mem/<size>` path, the size as 64 bit big endian.
+/// The perf server only sends zeros, so other content and options are usage errors
+fn perf_request(path: &str) -> Vec<u8> {
+    match MemRequest::from_str(path) {
+        Ok(r) if r.content == Content::Zeros && !path.contains('?') => (r.size as u64).to_be_bytes().to_vec(),
+        _ => client_usage_error(
+            ErrorKind::InvalidValue,
+            format!("the perf protocol requires /mem/<size> paths without options: {}", path),
+        ),
+    }
+}
+
+/// The file in `dir` named after the last segment of the request path, `index.html` if it is empty
+fn output_file_path(dir: &Path, path: &str) -> PathBuf {
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    let name = path.rsplit('/').next().filter(|n| !n.is_empty()).unwrap_or("index.html");
+    if matches!(name, "." | "..") {
+        panic!("cannot name an output file after the request path: {}", path);
+    }
+    dir.join(name)
+}
+
+/// Account the received test datagrams to their requests
+fn handle_dgrams(conn: &mut Conn<ConnAppData>, buf: &mut [u8], start_instant: Instant) {
+    let tunnel = conn.app_data.reqs.iter().any(|r| r.tunnel.is_some());
+    while let Ok(len) = conn.conn.dgram_recv(buf) {
+        let now = Instant::now();
+        // test datagrams are framed as HTTP datagrams, the UDP payloads of a tunnel with a context id
+        let payload = match tunnel {
+            true => parse_udp_dgram(&buf[..len]).map(|(_, payload)| payload),
+            false => parse_http_dgram(&buf[..len]).map(|(_, payload)| payload),
+        };
+        let Some((stream_id, seq, send_time)) = payload.and_then(dgram::read_header) else {
+            debug!("{} ignored datagram of {} bytes", conn.conn.trace_id(), len);
+            continue;
+        };
+        let Some(req) = conn.app_data.reqs.iter_mut().find(|r| r.stream_id == Some(stream_id)) else {
+            debug!("{} ignored datagram for unknown stream {}", conn.conn.trace_id(), stream_id);
+            continue;
+        };
+        let len = payload.unwrap().len();
+        req.dgram.get_or_insert_with(|| DgramStats::new(now))
+            .on_recv(len, seq, send_time, now - start_instant, now);
+    }
+}
+
+fn print_dgram_stats(label: &str, stats: &DgramStats) {
+    info!(
+        "recv dgrams {}: sent {}, received {}, lost {}, reordered {}, {} B, {:.6} s, {:.6} Gbps, jitter {:.3} ms",
+        label,
+        stats.sent.map_or("?".to_string(), |v| v.to_string()),
+        stats.received,
+        stats.lost().map_or("?".to_string(), |v| v.to_string()),
+        stats.reordered,
+        stats.received_bytes,
+        (stats.last_instant - stats.first_instant).as_secs_f64(),
+        stats.goodput() / 1E9,
+        stats.jitter.as_secs_f64() * 1E3,
+    );
+}
+
+fn print_total_results(results: &[RequestResult], start_instant: Instant, page_load: bool, socket_info: &SocketInfo) {
     let mut min_received_header_instant = INSTANT_MAX;
     let mut max_received_body_instant = INSTANT_ZERO;
     let mut sum_received_body_bytes = 0;
-    for req in reqs {
-        min_received_header_instant = min(req.received_header_instant.unwrap(), min_received_header_instant);
-        max_received_body_instant = max(req.received_body_instant.unwrap(), max_received_body_instant);
-        sum_received_body_bytes += req.received_body_bytes;
+    for result in results {
+        min_received_header_instant = min(result.received_header_instant, min_received_header_instant);
+        max_received_body_instant = max(result.received_body_instant, max_received_body_instant);
+        sum_received_body_bytes += result.received_body_bytes;
     }
     let duration = (max_received_body_instant - min_received_header_instant).as_secs_f64();
     let goodput = sum_received_body_bytes as f64 * 8f64 / duration;
     info!(
         "total: reqs {}, {} B, {:.6} s, {:.6} Gbps",
-        reqs.len(),
+        results.len(),
         sum_received_body_bytes,
         duration,
         goodput / 1E9,
     );
+    info!("socket: {}", socket_info);
+    if page_load {
+        info!(
+            "page load time: {:.6} s",
+            (max_received_body_instant - start_instant).as_secs_f64(),
+        );
+    }
+    if results.iter().any(|r| r.priority.is_some()) {
+        print_priority_results(results);
+    }
+    if results.iter().any(|r| r.verified) {
+        let mismatches = results.iter().filter(|r| r.first_mismatch.is_some()).count();
+        info!(
+            "verified: {} of {} bodies, {} mismatches",
+            results.iter().filter(|r| r.verified).count(),
+            results.len(),
+            mismatches,
+        );
+    }
+    if log::log_enabled!(Info) {
+        let mut results = results.to_vec();
+        results.sort_by_key(|r| r.entry);
+        for r in results {
+            info!(
+                "entry {} {}: {} B, header after {:.6} s, complete after {:.6} s{}",
+                r.entry,
+                r.path,
+                r.received_body_bytes,
+                (r.received_header_instant - start_instant).as_secs_f64(),
+                (r.received_body_instant - start_instant).as_secs_f64(),
+                match (r.verified, r.first_mismatch, r.expected_body_bytes) {
+                    (false, _, _) => String::new(),
+                    (true, None, _) => ", verified".to_string(),
+                    (true, Some(offset), Some(len)) if r.received_body_bytes != len => {
+                        format!(", first mismatch at offset {}, expected {} B", offset, len)
+                    }
+                    (true, Some(offset), _) => format!(", first mismatch at offset {}", offset),
+                },
+            );
+        }
+    }
+}
+
+/// Compare the completion order with the requested priorities.
+/// A pair of responses is inverted if the less urgent one completed first
+fn print_priority_results(results: &[RequestResult]) {
+    let urgency = |r: &RequestResult| r.priority.unwrap_or_default().urgency;
+    let mut inversions = 0;
+    for (i, a) in results.iter().enumerate() {
+        for b in &results[i + 1..] {
+            if urgency(a) > urgency(b) {
+                inversions += 1;
+            }
+        }
+    }
+    let pairs = results.len() * results.len().saturating_sub(1) / 2;
+    info!(
+        "completion order: [{}]",
+        results.iter()
+            .map(|r| format!("{} ({})", r.entry, r.priority.unwrap_or_default()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    info!("priority inversions: {} of {} pairs", inversions, pairs);
 }
 
 fn send_requests(endpoint: &mut Endpoint<ConnAppData, AppData>) {
+    let now = Instant::now();
     for i in endpoint.conn_index_iter() {
-        let Some(conn) = endpoint.conn(i) else {
+        let (conn, app_data) = endpoint.conn_with_app_data_mut(i);
+        let Some(conn) = conn else {
             continue
         };
-        // borrow mutable if necessary
-        let conn = if conn.app_data.h3_conn.is_none() {
+        if conn.conn.local_error().is_some() {
+            continue // closing
+        }
+        if app_data.protocol == Protocol::H3 && conn.app_data.h3_conn.is_none() {
             continue // not yet ready for h3
-        } else {
-            endpoint.conn_mut(i).unwrap()
-        };
-        let h3_conn = conn.app_data.h3_conn.as_mut().unwrap();
+        }
+        if app_data.protocol != Protocol::H3 && !conn.conn.is_established() && !conn.conn.is_in_early_data() {
+            continue // not yet ready
+        }
+        // none for the protocols without HTTP/3 framing
+        let mut h3_conn = conn.app_data.h3_conn.as_mut();
 
-        for req in conn.app_data.reqs.iter_mut().skip(conn.app_data.reqs_hdrs_sent) {
-            let stream_id = match h3_conn.send_request(
-                &mut conn.conn,
-                &req.hdrs,
-                true,
-            ) {
-                Ok(v) => v,
-                Err(h3::Error::TransportError(quiche::Error::StreamLimit)) => {
-                    continue // try again next time
-                }
-                Err(h3::Error::StreamBlocked) => {
-                    unimplemented!()
-                }
-                Err(_e) => {
-                    unimplemented!()
+        for req in conn.app_data.reqs.iter_mut() {
+            let stream_id = match req.stream_id {
+                Some(v) => v,
+                None => {
+                    if req.depends_on.is_some_and(|d| !app_data.entries_complete[d]) {
+                        continue // dependency not yet complete
+                    }
+                    let start_instant = app_data.start_instant + req.start_offset;
+                    if start_instant > now {
+                        app_data.wakeup.at(start_instant);
+                        continue // not yet due
+                    }
+                    let stream_id = match &mut h3_conn {
+                        Some(h3_conn) => match h3_conn.send_request(
+                            &mut conn.conn,
+                            &req.hdrs,
+                            req.body_size == 0 && req.tunnel.is_none(),
+                        ) {
+                            Ok(v) => v,
+                            Err(h3::Error::TransportError(quiche::Error::StreamLimit)) => {
+                                continue // try again next time
+                            }
+                            Err(h3::Error::StreamBlocked) => {
+                                unimplemented!()
+                            }
+                            Err(e) => {
+                                error!(
+                                    "{} error sending request ({} B header section): {:?}{}",
+                                    conn.conn.trace_id(),
+                                    field_section_size(&req.hdrs),
+                                    e,
+                                    error_hint(&e),
+                                );
+                                conn.conn.close(true, h3::WireErrorCode::InternalError as u64, b"").ok();
+                                break
+                            }
+                        },
+                        None => {
+                            // the stream is opened by sending the raw request below
+                            if conn.conn.peer_streams_left_bidi() == 0 {
+                                continue // try again next time
+                            }
+                            let stream_id = conn.app_data.next_stream_id;
+                            conn.app_data.next_stream_id += 4;
+                            stream_id
+                        }
+                    };
+                    match req.raw_request {
+                        None => info!("sent h3 req {:?} ({} B header section)", &req.hdrs, field_section_size(&req.hdrs)),
+                        Some(_) => info!(
+                            "sent {:?} req {} on stream {}",
+                            app_data.protocol,
+                            String::from_utf8_lossy(req.hdrs.iter().find(|h| h.name() == b":path").unwrap().value()),
+                            stream_id,
+                        ),
+                    }
+                    req.stream_id = Some(stream_id);
+                    if let Some(tunnel) = &mut req.tunnel {
+                        tunnel.sender.prefix = udp_dgram_prefix(stream_id);
+                    }
+                    stream_id
                 }
             };
-            info!("sent h3 req {:?}", &req.hdrs);
-            req.stream_id = Some(stream_id);
-            conn.app_data.reqs_hdrs_sent += 1;
 
-            //TODO support sending body
+            let raw_request = req.raw_request.as_deref().unwrap_or(&[]);
+            let total = raw_request.len() + req.body_size;
+            while req.body_sent < total {
+                let buf = if req.body_sent < raw_request.len() {
+                    &raw_request[req.body_sent..]
+                } else {
+                    &app_data.zeros[..min(total - req.body_sent, app_data.zeros.len())]
+                };
+                let fin = req.body_sent + buf.len() == total;
+                let written = match &mut h3_conn {
+                    Some(h3_conn) => match h3_conn.send_body(&mut conn.conn, stream_id, buf, fin) {
+                        Ok(v) => Ok(v),
+                        Err(h3::Error::Done) => Ok(0),
+                        Err(e) => Err(format!("{:?}", e)),
+                    },
+                    None => match conn.conn.stream_send(stream_id, buf, fin) {
+                        Ok(v) => Ok(v),
+                        Err(quiche::Error::Done) => Ok(0),
+                        Err(e) => Err(format!("{:?}", e)),
+                    },
+                };
+                match written {
+                    Ok(written) => {
+                        req.body_sent += written;
+                        if written < buf.len() {
+                            break // try again next time
+                        }
+                    }
+                    Err(e) => {
+                        error!("{} request body send failed {}", conn.conn.trace_id(), e);
+                        break
+                    }
+                }
+            }
+
+            if let Some(tunnel) = &mut req.tunnel
+                && let Some(h3_conn) = &mut h3_conn
+                && !tunnel.closed
+                && req.status.is_some_and(|s| (200..300).contains(&s)) {
+                if tunnel.start_wire_bytes.is_none() {
+                    let quic_stats = conn.conn.stats();
+                    tunnel.start_wire_bytes = Some(quic_stats.sent_bytes + quic_stats.recv_bytes);
+                }
+                if tunnel.sent_instant.is_none() {
+                    match tunnel.sender.send(&mut conn.conn, stream_id, &mut app_data.dgram_buf) {
+                        Ok(true) => tunnel.sent_instant = Some(now),
+                        Ok(false) => {
+                            if let Some(next) = tunnel.sender.next_send_instant().filter(|i| *i > now) {
+                                // the runner only wakes up on socket events and connection timeouts
+                                app_data.wakeup.at(next);
+                            }
+                        }
+                        Err(e) => {
+                            error!("{} {}, check --udp-size", conn.conn.trace_id(), e);
+                            conn.conn.close(true, h3::WireErrorCode::InternalError as u64, b"").ok();
+                            break
+                        }
+                    }
+                }
+                let received = req.dgram.as_ref().map_or(0, |d| d.received);
+                match tunnel.sent_instant {
+                    Some(i) if received == tunnel.sender.seq || now - i >= DRAIN_TIMEOUT => {
+                        // the server closes its side of the stream in turn, which completes the request
+                        if h3_conn.send_body(&mut conn.conn, stream_id, &[], true).is_ok() {
+                            tunnel.closed = true;
+                        }
+                    }
+                    // arriving datagrams wake the runner, stop waiting for the lost ones
+                    Some(i) => app_data.wakeup.at(i + DRAIN_TIMEOUT),
+                    None => {}
+                }
+            }
         }
     }
 }
@@ -343,10 +1084,14 @@ struct PrettyConnectionError<'a>(&'a ConnectionError);
 
 impl<'a> Debug for PrettyConnectionError<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("ConnectionError")
-            .field("is_app", &self.0.is_app)
-            .field("error_code", &self.0.error_code)
-            .field("reason", &String::from_utf8_lossy(&self.0.reason))
+        let mut d = f.debug_struct("ConnectionError");
+        d.field("is_app", &self.0.is_app)
+            .field("error_code", &self.0.error_code);
+        if !self.0.is_app
+            && let Some(alert) = tls_alert_description(self.0.error_code) {
+            d.field("tls_alert", &alert);
+        }
+        d.field("reason", &String::from_utf8_lossy(&self.0.reason))
             .finish()
     }
 }
@@ -0,0 +1,181 @@
+use std::cmp::min;
+
+/// Content of a generated body.
+///
+/// The content at any offset can be generated independently,
+/// so the server can generate each chunk on the fly
+/// and the client can verify each received chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Content {
+    /// zero bytes
+    Zeros,
+    /// bytes 0 to 255, repeating
+    Pattern,
+    /// pseudo random bytes, reproducible by the seed
+    Random(u64),
+}
+
+impl Content {
+    /// Fill `buf` with the content starting at `offset`
+    pub fn fill(&self, offset: usize, buf: &mut [u8]) {
+        match self {
+            Content::Zeros => buf.fill(0),
+            Content::Pattern => {
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = (offset + i) as u8;
+                }
+            }
+            Content::Random(seed) => fill_random(*seed, offset, buf),
+        }
+    }
+
+    /// Return the index of the first byte of `data` that differs from the content starting at `offset`
+    pub fn first_mismatch(&self, offset: usize, data: &[u8]) -> Option<usize> {
+        match self {
+            Content::Zeros => data.iter().position(|b| *b != 0),
+            Content::Pattern => data.iter()
+                .enumerate()
+                .position(|(i, b)| *b != (offset + i) as u8),
+            Content::Random(_) => {
+                let mut expected = [0u8; 4096];
+                let mut checked = 0;
+                for chunk in data.chunks(expected.len()) {
+                    let expected = &mut expected[..chunk.len()];
+                    self.fill(offset + checked, expected);
+                    if let Some(i) = chunk.iter().zip(expected.iter()).position(|(a, b)| a != b) {
+                        return Some(checked + i);
+                    }
+                    checked += chunk.len();
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Verifies a received body chunk by chunk against the content and length of a generated body
+#[derive(Clone, Copy, Debug)]
+pub struct Verifier {
+    content: Content,
+    len: usize,
+    received: usize,
+    first_mismatch: Option<usize>,
+}
+
+impl Verifier {
+    pub fn new(content: Content, len: usize) -> Self {
+        Self { content, len, received: 0, first_mismatch: None }
+    }
+
+    /// Length of the body
+    pub fn expected_len(&self) -> usize {
+        self.len
+    }
+
+    /// Verify the next chunk of the body.
+    /// Return the offset of the first mismatch, if it is in this chunk
+    pub fn update(&mut self, data: &[u8]) -> Option<usize> {
+        let offset = self.received;
+        self.received += data.len();
+        if self.first_mismatch.is_some() {
+            return None;
+        }
+        // bytes beyond the expected length do not match any content
+        let expected_len = min(data.len(), self.len.saturating_sub(offset));
+        self.first_mismatch = self.content.first_mismatch(offset, &data[..expected_len])
+            .or((expected_len < data.len()).then_some(expected_len))
+            .map(|i| offset + i);
+        self.first_mismatch
+    }
+
+    /// Finish the verification of the complete body.
+    /// Return the offset of the first mismatch, the end of the body if it is truncated
+    pub fn finish(&mut self) -> Option<usize> {
+        if self.first_mismatch.is_none() && self.received < self.len {
+            self.first_mismatch = Some(self.received);
+        }
+        self.first_mismatch
+    }
+}
+
+/// Every 8 byte word of the stream is the splitmix64 output for the word index,
+/// so generation can start at any offset
+fn random_word(seed: u64, index: u64) -> [u8; 8] {
+    let mut z = seed.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9E3779B97F4A7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    (z ^ (z >> 31)).to_le_bytes()
+}
+
+fn fill_random(seed: u64, offset: usize, buf: &mut [u8]) {
+    let mut index = (offset / 8) as u64;
+    // unaligned head
+    let start = offset % 8;
+    let mut head = 0;
+    if start != 0 {
+        head = (8 - start).min(buf.len());
+        buf[..head].copy_from_slice(&random_word(seed, index)[start..start + head]);
+        index += 1;
+    }
+    let mut chunks = buf[head..].chunks_exact_mut(8);
+    for chunk in &mut chunks {
+        chunk.copy_from_slice(&random_word(seed, index));
+        index += 1;
+    }
+    // unaligned tail
+    let tail = chunks.into_remainder();
+    if !tail.is_empty() {
+        let word = random_word(seed, index);
+        let len = tail.len();
+        tail.copy_from_slice(&word[..len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(content: Content, len: usize) -> Vec<u8> {
+        let mut body = vec![0; len];
+        content.fill(0, &mut body);
+        body
+    }
+
+    #[test]
+    fn verify_complete_body() {
+        let content = Content::Random(42);
+        let body = body(content, 10_000);
+        let mut verifier = Verifier::new(content, body.len());
+        for chunk in body.chunks(1_001) {
+            assert_eq!(verifier.update(chunk), None);
+        }
+        assert_eq!(verifier.finish(), None);
+    }
+
+    #[test]
+    fn verify_truncated_body() {
+        let body = body(Content::Pattern, 1000);
+        let mut verifier = Verifier::new(Content::Pattern, 1000);
+        assert_eq!(verifier.update(&body[..600]), None);
+        assert_eq!(verifier.finish(), Some(600));
+    }
+
+    #[test]
+    fn verify_too_long_body() {
+        let body = body(Content::Pattern, 1000);
+        let mut verifier = Verifier::new(Content::Pattern, 600);
+        assert_eq!(verifier.update(&body[..500]), None);
+        assert_eq!(verifier.update(&body[500..]), Some(600));
+        assert_eq!(verifier.finish(), Some(600));
+    }
+
+    #[test]
+    fn verify_modified_body() {
+        let mut body = body(Content::Random(1), 1000);
+        body[700] ^= 1;
+        let mut verifier = Verifier::new(Content::Random(1), 1000);
+        assert_eq!(verifier.update(&body[..500]), None);
+        assert_eq!(verifier.update(&body[500..]), Some(700));
+        assert_eq!(verifier.finish(), Some(700));
+    }
+}
@@ -0,0 +1,190 @@
+use quiche_mio_runner::quiche_endpoint::quiche;
+use std::cmp::min;
+use std::time::{Duration, Instant};
+
+/// Length of the header of each test datagram:
+/// the id of the request stream, the sequence number and the send timestamp in nanoseconds,
+/// all 64 bit big endian
+pub const HEADER_LEN: usize = 24;
+
+/// Write the header of a test datagram to the start of `buf`
+pub fn write_header(buf: &mut [u8], stream_id: u64, seq: u64, timestamp: Duration) {
+    buf[0..8].copy_from_slice(&stream_id.to_be_bytes());
+    buf[8..16].copy_from_slice(&seq.to_be_bytes());
+    buf[16..24].copy_from_slice(&(timestamp.as_nanos() as u64).to_be_bytes());
+}
+
+/// Read the stream id, sequence number and send timestamp of a test datagram
+pub fn read_header(buf: &[u8]) -> Option<(u64, u64, Duration)> {
+    if buf.len() < HEADER_LEN {
+        return None;
+    }
+    let stream_id = u64::from_be_bytes(buf[0..8].try_into().unwrap());
+    let seq = u64::from_be_bytes(buf[8..16].try_into().unwrap());
+    let timestamp = Duration::from_nanos(u64::from_be_bytes(buf[16..24].try_into().unwrap()));
+    Some((stream_id, seq, timestamp))
+}
+
+/// Receiver statistics of the test datagrams of one request
+#[derive(Clone, Debug)]
+pub struct DgramStats {
+    /// number of datagrams the sender reported, once known
+    pub sent: Option<u64>,
+    pub received: u64,
+    pub received_bytes: usize,
+    /// datagrams received after one with a higher sequence number
+    pub reordered: u64,
+    pub first_instant: Instant,
+    pub last_instant: Instant,
+    /// smoothed variation of the one-way delay, RFC 3550 section 6.4.1
+    pub jitter: Duration,
+    max_seq: Option<u64>,
+    /// one-way delay of the previous datagram, including the unknown clock offset
+    prev_transit: Option<f64>,
+    jitter_secs: f64,
+}
+
+impl DgramStats {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            sent: None,
+            received: 0,
+            received_bytes: 0,
+            reordered: 0,
+            first_instant: now,
+            last_instant: now,
+            jitter: Duration::ZERO,
+            max_seq: None,
+            prev_transit: None,
+            jitter_secs: 0.0,
+        }
+    }
+
+    /// Account a received datagram.
+    /// `recv_time` and `send_time` only need to be relative to the same instant on each side
+    pub fn on_recv(&mut self, len: usize, seq: u64, send_time: Duration, recv_time: Duration, now: Instant) {
+        self.received += 1;
+        self.received_bytes += len;
+        self.last_instant = now;
+        match self.max_seq {
+            Some(max_seq) if seq < max_seq => self.reordered += 1,
+            _ => self.max_seq = Some(seq),
+        }
+        // the clock offset cancels out in the difference of consecutive transit times
+        let transit = recv_time.as_secs_f64() - send_time.as_secs_f64();
+        if let Some(prev_transit) = self.prev_transit {
+            let d = (transit - prev_transit).abs();
+            self.jitter_secs += (d - self.jitter_secs) / 16.0;
+            self.jitter = Duration::from_secs_f64(self.jitter_secs);
+        }
+        self.prev_transit = Some(transit);
+    }
+
+    /// Number of datagrams that did not arrive, once the number of sent datagrams is known
+    pub fn lost(&self) -> Option<u64> {
+        self.sent.map(|sent| sent.saturating_sub(self.received))
+    }
+
+    /// Received bits per second between the first and the last datagram,
+    /// 0 until datagrams arrived over some time
+    pub fn goodput(&self) -> f64 {
+        let secs = (self.last_instant - self.first_instant).as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.received_bytes as f64 * 8.0 / secs
+    }
+}
+
+/// bytes a rate-limited sender may send ahead of its rate
+const RATE_BURST: usize = 16_384;
+
+/// Sends the test datagrams of a request
+pub struct DgramSender {
+    /// written before the test header of each datagram, e.g. the HTTP datagram framing
+    pub prefix: Vec<u8>,
+    /// total payload bytes to send, without the prefix
+    pub total: usize,
+    /// payload bytes per datagram, the max the path allows if none
+    pub size: Option<usize>,
+    /// payload rate in bytes per second, as fast as the congestion control allows if none
+    pub rate: Option<u64>,
+    pub sent: usize,
+    pub seq: u64,
+    start_instant: Option<Instant>,
+}
+
+impl DgramSender {
+    pub fn new(total: usize, size: Option<usize>, rate: Option<u64>) -> Self {
+        Self {
+            prefix: vec![],
+            total,
+            size,
+            rate,
+            sent: 0,
+            seq: 0,
+            start_instant: None,
+        }
+    }
+
+    /// When the rate allows to send the next datagram,
+    /// none if the datagrams are not rate-limited, not yet started or all sent
+    pub fn next_send_instant(&self) -> Option<Instant> {
+        let (rate, start_instant) = (self.rate?, self.start_instant?);
+        if self.sent >= self.total {
+            return None;
+        }
+        let bytes = (self.sent + 1).saturating_sub(RATE_BURST);
+        Some(start_instant + Duration::from_secs_f64(bytes as f64 / rate as f64))
+    }
+
+    /// Queue datagrams until the send queue is full or the rate is reached.
+    /// Return true if all datagrams are sent,
+    /// an error if they cannot be sent, e.g. if they are larger than the connection allows
+    pub fn send(&mut self, quic_conn: &mut quiche::Connection, stream_id: u64, buf: &mut [u8]) -> Result<bool, String> {
+        let now = Instant::now();
+        let start_instant = *self.start_instant.get_or_insert(now);
+        let prefix_len = self.prefix.len();
+        buf[..prefix_len].copy_from_slice(&self.prefix);
+        while self.sent < self.total {
+            if let Some(rate) = self.rate {
+                let allowed = (rate as f64 * (now - start_instant).as_secs_f64()) as usize + RATE_BURST;
+                if self.sent >= allowed {
+                    return Ok(false); // not yet due
+                }
+            }
+            let Some(max_len) = quic_conn.dgram_max_writable_len() else {
+                return Ok(false); // not yet known
+            };
+            let max_payload = max_len.saturating_sub(prefix_len);
+            if self.size.unwrap_or(HEADER_LEN) > max_payload {
+                return Err(format!(
+                    "datagrams of {} bytes exceed the max of {} bytes the connection allows",
+                    self.size.unwrap_or(HEADER_LEN),
+                    max_payload,
+                ));
+            }
+            let len = min(self.size.unwrap_or(max_payload), buf.len() - prefix_len)
+                .min(self.total - self.sent)
+                .max(HEADER_LEN);
+            let payload = &mut buf[prefix_len..prefix_len + len];
+            write_header(payload, stream_id, self.seq, now - start_instant);
+            payload[HEADER_LEN..].fill(0);
+            match quic_conn.dgram_send(&buf[..prefix_len + len]) {
+                Ok(_) => {
+                    self.sent += len;
+                    self.seq += 1;
+                }
+                Err(quiche::Error::Done) => return Ok(false), // queue full, try again next time
+                Err(e) => return Err(format!("failed to send datagram of {} bytes: {:?}", prefix_len + len, e)),
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Max number of received datagrams queued until read, more are dropped
+pub const RECV_QUEUE_LEN: usize = 65_536;
+/// Max number of datagrams queued until sent.
+/// Kept short so the send timestamps are close to the actual send time
+pub const SEND_QUEUE_LEN: usize = 64;
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Component, Path, PathBuf};
+
+/// The directories to serve files from, per host
+#[derive(Debug, Default)]
+pub struct Roots {
+    /// canonical directory for requests to other hosts
+    pub default: Option<PathBuf>,
+    /// canonical directories keyed by lowercase host name
+    pub hosts: HashMap<String, PathBuf>,
+}
+
+impl Roots {
+    /// The root for a request to the `:authority`, the default root if none is configured for its host
+    pub fn get(&self, authority: Option<&str>) -> Option<&Path> {
+        authority.map(|a| host_of(a).to_ascii_lowercase())
+            .and_then(|host| self.hosts.get(&host))
+            .or(self.default.as_ref())
+            .map(PathBuf::as_path)
+    }
+}
+
+/// The host of an authority, without the port
+fn host_of(authority: &str) -> &str {
+    if authority.starts_with('[') {
+        // IPv6 literal
+        return authority.split_once(']').map_or(authority, |(host, _)| &authority[..host.len() + 1]);
+    }
+    match authority.rsplit_once(':') {
+        Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => authority,
+    }
+}
+
+/// Open the file at the request path below `root`.
+/// `root` must be canonical.
+/// Return none if there is no such file or if the path leaves `root`, e.g. by `..` or symlinks
+pub fn open_file(root: &Path, request_path: &str) -> Option<(File, usize)> {
+    let path = resolve(root, request_path)?;
+    let file = File::open(&path).ok()?;
+    let metadata = file.metadata().ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    Some((file, metadata.len() as usize))
+}
+
+fn resolve(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let request_path = request_path.split(['?', '#']).next()?;
+    let request_path = percent_decode(request_path)?;
+    let relative = Path::new(request_path.trim_start_matches('/'));
+    if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    let path = root.join(relative).canonicalize().ok()?;
+    // symlinks might still point outside the root
+    if !path.starts_with(root) {
+        return None;
+    }
+    Some(path)
+}
+
+/// Decode a percent-encoded path.
+/// Return none if it is malformed or contains encoded separators, backslashes or NUL bytes
+fn percent_decode(s: &str) -> Option<String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            let decoded = u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?;
+            if decoded == b'/' {
+                return None;
+            }
+            out.push(decoded);
+        } else {
+            out.push(b);
+        }
+    }
+    let s = String::from_utf8(out).ok()?;
+    if s.contains(['\0', '\\']) {
+        return None;
+    }
+    Some(s)
+}
+
+/// Guess the content type from the file extension
+pub fn content_type(path: &str) -> &'static str {
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    let extension = path.rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html",
+        Some("css") => "text/css",
+        Some("js" | "mjs") => "text/javascript",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("wasm") => "application/wasm",
+        Some("mp4" | "m4s" | "m4v") => "video/mp4",
+        Some("m4a") => "audio/mp4",
+        Some("webm") => "video/webm",
+        Some("ts") => "video/mp2t",
+        Some("mpd") => "application/dash+xml",
+        Some("m3u8") => "application/vnd.apple.mpegurl",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A canonical root with `dir/file.txt`, next to `secret.txt` that `link` in the root points to
+    fn root(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("quiche_perf_{}_files_{}", std::process::id(), name));
+        fs::create_dir_all(dir.join("root/dir")).unwrap();
+        fs::write(dir.join("root/dir/file.txt"), "file\n").unwrap();
+        fs::write(dir.join("secret.txt"), "secret\n").unwrap();
+        std::os::unix::fs::symlink(dir.join("secret.txt"), dir.join("root/link")).unwrap();
+        dir.join("root").canonicalize().unwrap()
+    }
+
+    #[test]
+    fn decode_paths() {
+        assert_eq!(percent_decode("/a%20b%2e").as_deref(), Some("/a b."));
+        for s in ["/a%2Fb", "/a%2fb", "/a%5Cb", "/a\\b", "/a%00", "/a%2", "/a%zz"] {
+            assert_eq!(percent_decode(s), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn open_files_below_root() {
+        let root = root("open_files_below_root");
+        let opened = open_file(&root, "/dir/file.txt?query").map(|(_, len)| len);
+        let rejected = [
+            "/../secret.txt",
+            "/dir/../../secret.txt",
+            "/%2e%2e/secret.txt",
+            "/dir%2F..%2F..%2Fsecret.txt",
+            "/link",
+            "/dir",
+            "/missing.txt",
+        ].map(|path| (path, open_file(&root, path).is_some()));
+        fs::remove_dir_all(root.parent().unwrap()).unwrap();
+        assert_eq!(opened, Some(5));
+        for (path, is_some) in rejected {
+            assert!(!is_some, "{}", path);
+        }
+    }
+}
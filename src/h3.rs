@@ -1,5 +1,7 @@
 use quiche_mio_runner::quiche_endpoint::quiche;
 use quiche_mio_runner::quiche_endpoint::quiche::h3::NameValue;
+use crate::args::H3Args;
+use log::info;
 
 /// get human readable headers, for debugging or logging
 pub fn hdrs_to_strings(hdrs: &[quiche::h3::Header]) -> Vec<(String, String)> {
@@ -12,3 +14,87 @@ pub fn hdrs_to_strings(hdrs: &[quiche::h3::Header]) -> Vec<(String, String)> {
         })
         .collect()
 }
+
+/// Size of a header section as limited by the `max_field_section_size` setting,
+/// counting 32 bytes of overhead per header, RFC 9114 section 4.2.2
+pub fn field_section_size<T: NameValue>(hdrs: &[T]) -> usize {
+    hdrs.iter().map(|h| h.name().len() + h.value().len() + 32).sum()
+}
+
+/// Generate `count` headers with names and values of `size` bytes in total,
+/// or of about 1 KiB each if `count` is none.
+/// The values cycle through alphanumeric characters, so they are not trivially compressible
+pub fn filler_headers(size: usize, count: Option<usize>) -> Vec<quiche::h3::Header> {
+    const CHARS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let count = count.unwrap_or(size.div_ceil(1024));
+    let mut remaining = size;
+    (0..count)
+        .map(|i| {
+            let name = format!("x-filler-{}", i);
+            let len = (remaining / (count - i)).saturating_sub(name.len());
+            remaining = remaining.saturating_sub(name.len() + len);
+            let value = (0..len).map(|j| CHARS[(i + j) % CHARS.len()]).collect::<Vec<u8>>();
+            quiche::h3::Header::new(name.as_bytes(), &value)
+        })
+        .collect()
+}
+
+/// Build the HTTP/3 config from the command line settings
+pub fn h3_config(args: &H3Args) -> quiche::h3::Config {
+    let mut c = quiche::h3::Config::new().unwrap();
+    if let Some(v) = args.max_field_section_size {
+        c.set_max_field_section_size(v);
+    }
+    if let Some(v) = args.qpack_max_table_capacity {
+        c.set_qpack_max_table_capacity(v);
+    }
+    if let Some(v) = args.qpack_blocked_streams {
+        c.set_qpack_blocked_streams(v);
+    }
+    c.enable_extended_connect(args.enable_extended_connect);
+    c
+}
+
+/// get human readable settings with the names of the known identifiers, for logging
+pub fn settings_to_string(settings: &[(u64, u64)]) -> String {
+    settings.iter()
+        .map(|(id, value)| {
+            let name = match id {
+                0x1 => "QPACK_MAX_TABLE_CAPACITY".to_string(),
+                0x6 => "MAX_FIELD_SECTION_SIZE".to_string(),
+                0x7 => "QPACK_BLOCKED_STREAMS".to_string(),
+                0x8 => "ENABLE_CONNECT_PROTOCOL".to_string(),
+                0x33 => "H3_DATAGRAM".to_string(),
+                0xffd277 => "H3_DATAGRAM_DRAFT".to_string(),
+                // reserved identifiers of the form 0x1f * N + 0x21
+                id if *id >= 0x21 && (id - 0x21) % 0x1f == 0 => format!("GREASE({:#x})", id),
+                id => format!("{:#x}", id),
+            };
+            format!("{}={}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Log the settings of the peer once they arrived.
+/// Return true if they were logged
+pub fn log_peer_settings(trace_id: &str, h3_conn: &quiche::h3::Connection) -> bool {
+    let Some(settings) = h3_conn.peer_settings_raw() else {
+        return false; // not yet received
+    };
+    info!("{} peer settings: {}", trace_id, settings_to_string(settings));
+    true
+}
+
+/// Explain HTTP/3 errors that are usually caused by the header sizes or the settings
+pub fn error_hint(e: &quiche::h3::Error) -> &'static str {
+    match e {
+        quiche::h3::Error::ExcessiveLoad => {
+            "; a header section might exceed the max_field_section_size setting"
+        }
+        quiche::h3::Error::QpackDecompressionFailed => {
+            "; the encoder might exceed the QPACK table capacity or blocked streams settings"
+        }
+        _ => "",
+    }
+}
@@ -0,0 +1,173 @@
+use crate::workload::{Workload, WorkloadEntry};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Map the entries of a HAR file to a workload of `/mem/<size>` requests.
+///
+/// The response body sizes, start time offsets and connections are taken from the HAR entries.
+/// Each request depends on the latest request that completed before it was started,
+/// so the request order of the page load is kept, even if the replay is slower or faster.
+pub fn workload_from_har(path: &Path) -> Result<Workload, String> {
+    let s = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    parse_har(&s)
+}
+
+/// Like [`workload_from_har`], for the content of a HAR file
+fn parse_har(s: &str) -> Result<Workload, String> {
+    let har: Value = serde_json::from_str(s).map_err(|e| e.to_string())?;
+    let entries = har["log"]["entries"].as_array().ok_or("missing log.entries")?;
+
+    struct HarEntry {
+        start_ms: f64,
+        end_ms: f64,
+        method: String,
+        conn: usize,
+        req_body_size: usize,
+        resp_body_size: usize,
+    }
+
+    let mut conns = HashMap::new();
+    let mut har_entries = Vec::with_capacity(entries.len());
+    for (i, e) in entries.iter().enumerate() {
+        let started = e["startedDateTime"].as_str()
+            .ok_or_else(|| format!("entry {}: missing startedDateTime", i))?;
+        let start_ms = parse_date_time_ms(started)
+            .ok_or_else(|| format!("entry {}: invalid startedDateTime: {}", i, started))?;
+        let time_ms = e["time"].as_f64().unwrap_or(0.0).max(0.0);
+        // connection ids are only unique within the HAR file
+        let conn_key = e["connection"].as_str().unwrap_or("").to_string();
+        let next_conn = conns.len();
+        let conn = *conns.entry(conn_key).or_insert(next_conn);
+        har_entries.push(HarEntry {
+            start_ms,
+            end_ms: start_ms + time_ms,
+            method: e["request"]["method"].as_str().unwrap_or("GET").to_string(),
+            conn,
+            req_body_size: size_field(&e["request"]["bodySize"]).unwrap_or(0),
+            // bodySize is -1 if unknown, e.g. for cached responses
+            resp_body_size: size_field(&e["response"]["bodySize"])
+                .or_else(|| size_field(&e["response"]["content"]["size"]))
+                .unwrap_or(0),
+        });
+    }
+    let page_start_ms = har_entries.iter()
+        .map(|e| e.start_ms)
+        .reduce(f64::min)
+        .ok_or("no entries")?;
+
+    let workload_entries = har_entries.iter()
+        .enumerate()
+        .map(|(i, e)| {
+            let depends_on = har_entries.iter()
+                .enumerate()
+                .filter(|(j, d)| *j != i && d.start_ms < e.start_ms && d.end_ms <= e.start_ms)
+                .max_by(|(_, a), (_, b)| a.end_ms.total_cmp(&b.end_ms))
+                .map(|(j, _)| j);
+            WorkloadEntry {
+                conn: e.conn,
+                method: e.method.clone(),
+                path: format!("/mem/{}", e.resp_body_size),
+                body_size: e.req_body_size,
+                start_offset: Duration::from_secs_f64((e.start_ms - page_start_ms) / 1E3),
+                depends_on,
+                priority: None,
+            }
+        })
+        .collect();
+
+    Ok(Workload { entries: workload_entries })
+}
+
+fn size_field(v: &Value) -> Option<usize> {
+    v.as_i64().filter(|v| *v >= 0).map(|v| v as usize)
+}
+
+/// Parse an ISO 8601 date time like `2024-05-06T07:08:09.123+02:00`
+/// to milliseconds since the unix epoch
+fn parse_date_time_ms(s: &str) -> Option<f64> {
+    let (date, time) = s.split_once('T')?;
+    let mut date = date.splitn(3, '-');
+    let year = date.next()?.parse::<i64>().ok()?;
+    let month = date.next()?.parse::<i64>().ok()?;
+    let day = date.next()?.parse::<i64>().ok()?;
+
+    let (time, offset_s) = if let Some(time) = time.strip_suffix('Z') {
+        (time, 0)
+    } else {
+        let i = time.rfind(['+', '-'])?;
+        let (time, offset) = time.split_at(i);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let offset = &offset[1..];
+        let (h, m) = offset.split_once(':').or_else(|| Some((offset.get(..2)?, offset.get(2..)?)))?;
+        (time, sign * (h.parse::<i64>().ok()? * 3600 + m.parse::<i64>().ok()? * 60))
+    };
+    let mut time = time.splitn(3, ':');
+    let hours = time.next()?.parse::<i64>().ok()?;
+    let minutes = time.next()?.parse::<i64>().ok()?;
+    let seconds = time.next()?.parse::<f64>().ok()?;
+
+    // days since the unix epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let secs = days * 86400 + hours * 3600 + minutes * 60 - offset_s;
+    Some(secs as f64 * 1E3 + seconds * 1E3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(started: &str, time: f64, connection: &str, size: i64) -> String {
+        format!(
+            r#"{{"startedDateTime": "{}", "time": {}, "connection": "{}", "request": {{"method": "GET"}}, "response": {{"bodySize": {}, "content": {{"size": 42}}}}}}"#,
+            started, time, connection, size,
+        )
+    }
+
+    #[test]
+    fn map_entries() {
+        let har = format!(
+            r#"{{"log": {{"entries": [{}, {}, {}]}}}}"#,
+            entry("2024-05-06T07:08:09.000Z", 100.0, "1", 1000),
+            // started before the first entry completed, cached body of unknown size
+            entry("2024-05-06T07:08:09.050Z", 100.0, "2", -1),
+            entry("2024-05-06T07:08:09.200Z", 10.0, "1", 0),
+        );
+        let workload = parse_har(&har).unwrap();
+        let e = &workload.entries;
+        assert_eq!(e.iter().map(|e| e.path.as_str()).collect::<Vec<_>>(), ["/mem/1000", "/mem/42", "/mem/0"]);
+        assert_eq!(e.iter().map(|e| e.conn).collect::<Vec<_>>(), [0, 1, 0]);
+        assert_eq!(e[1].start_offset, Duration::from_millis(50));
+        assert_eq!(e[2].start_offset, Duration::from_millis(200));
+        assert_eq!(e.iter().map(|e| e.depends_on).collect::<Vec<_>>(), [None, None, Some(1)]);
+    }
+
+    #[test]
+    fn reject_malformed() {
+        assert_eq!(parse_har(r#"{"log": {}}"#).unwrap_err(), "missing log.entries");
+        assert_eq!(parse_har(r#"{"log": {"entries": []}}"#).unwrap_err(), "no entries");
+        let har = format!(r#"{{"log": {{"entries": [{}]}}}}"#, entry("yesterday", 1.0, "1", 0));
+        assert_eq!(parse_har(&har).unwrap_err(), "entry 0: invalid startedDateTime: yesterday");
+    }
+
+    #[test]
+    fn parse_date_time_with_offset() {
+        let utc = parse_date_time_ms("2024-05-06T05:08:09.123Z").unwrap();
+        assert!((utc - 1_714_972_089_123.0).abs() < 1E-3);
+        assert_eq!(parse_date_time_ms("2024-05-06T07:08:09.123+02:00"), Some(utc));
+        assert_eq!(parse_date_time_ms("2024-05-06T07:08:09.123+0200"), Some(utc));
+        assert_eq!(parse_date_time_ms("2024-05-05T23:38:09.123-05:30"), Some(utc));
+        assert_eq!(parse_date_time_ms("2000-02-29T23:59:59Z"), Some(951_868_799_000.0));
+        assert_eq!(parse_date_time_ms("1970-01-01T00:00:00Z"), Some(0.0));
+        assert_eq!(parse_date_time_ms("2024-05-06T07:08:09"), None);
+        assert_eq!(parse_date_time_ms("2024-05-06"), None);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::content::Content;
+use crate::files::{open_file, Roots};
+use crate::mem::{MemRequest, MemRequestError};
+use log::{error, info};
+use quiche_mio_runner::quiche_endpoint::quiche;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::str::{from_utf8, FromStr};
+
+/// ALPN of HTTP/0.9 over QUIC, as spoken in the QUIC Interop Runner
+pub const APPLICATION_PROTOCOL: &[&[u8]] = &[b"hq-interop"];
+
+/// Max length of a request line, longer requests are rejected
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// The request line of a resource, `GET <path>\r\n`
+pub fn request_line(path: &str) -> Vec<u8> {
+    format!("GET {}\r\n", path).into_bytes()
+}
+
+/// A response of the hq-interop server, keyed by the stream id
+#[derive(Default)]
+pub struct HqResponse {
+    request: Vec<u8>,
+    request_fin: bool,
+    /// some once the request line is complete and the resource exists
+    body: Option<HqBody>,
+    written: usize,
+    /// whether the response is complete or the stream is reset
+    done: bool,
+}
+
+enum HqBody {
+    /// generated content of specified length
+    Generated(usize, Content),
+    /// file content of specified length, read chunk by chunk
+    File(File, usize),
+}
+
+impl HqBody {
+    fn len(&self) -> usize {
+        match self {
+            HqBody::Generated(l, _) => *l,
+            HqBody::File(_, l) => *l,
+        }
+    }
+}
+
+/// Resolve a request line to the generated `/mem` resources or the files below `root`
+fn build_body(request: &[u8], root: Option<&Path>) -> Result<HqBody, String> {
+    let line = from_utf8(request).map_err(|_| "request is not UTF-8".to_string())?;
+    let path = line.trim_end()
+        .strip_prefix("GET ")
+        .map(str::trim)
+        .filter(|p| p.starts_with('/'))
+        .ok_or_else(|| format!("expected `GET <path>`: {:?}", line))?;
+    match MemRequest::from_str(path) {
+        Ok(r) => Ok(HqBody::Generated(r.size, r.content)),
+        Err(MemRequestError::BadRequest(e)) => Err(format!("{}: {}", path, e)),
+        Err(MemRequestError::NotFound) => root.and_then(|root| open_file(root, path))
+            .map(|(file, len)| HqBody::File(file, len))
+            .ok_or_else(|| format!("not found: {}", path)),
+    }
+}
+
+/// Read the request lines and send the requested resources on each stream,
+/// the files below the root of the host named by the SNI, as requests carry no authority.
+/// Streams of requests that cannot be served are reset
+pub fn handle_server_streams(quic_conn: &mut quiche::Connection, responses: &mut HashMap<u64, HqResponse>, buf: &mut [u8], scratch_buf: &mut [u8], roots: &Roots) {
+    for stream_id in quic_conn.readable().collect::<Vec<u64>>() {
+        let resp = responses.entry(stream_id).or_default();
+        loop {
+            match quic_conn.stream_recv(stream_id, buf) {
+                Ok((len, fin)) => {
+                    if resp.body.is_none() && !resp.done {
+                        resp.request.extend_from_slice(&buf[..len]);
+                    }
+                    if fin {
+                        resp.request_fin = true;
+                        break;
+                    }
+                }
+                Err(quiche::Error::Done) => break,
+                Err(e) => {
+                    error!("{} failed to receive on stream {}: {:?}", quic_conn.trace_id(), stream_id, e);
+                    resp.request_fin = true;
+                    resp.done = true;
+                    break;
+                }
+            }
+        }
+        if resp.body.is_some() || resp.done {
+            continue; // anything after the request line is ignored
+        }
+        let line_end = resp.request.iter().position(|b| *b == b'\n');
+        if line_end.is_none() && !resp.request_fin && resp.request.len() <= MAX_REQUEST_LEN {
+            continue; // request line not yet complete
+        }
+        match build_body(&resp.request[..line_end.unwrap_or(resp.request.len())], roots.get(quic_conn.server_name())) {
+            Ok(body) => {
+                info!("{} hq req on stream {}: {}", quic_conn.trace_id(), stream_id, String::from_utf8_lossy(&resp.request).trim_end());
+                resp.body = Some(body);
+            }
+            Err(e) => {
+                info!("{} reset hq stream {}, {}", quic_conn.trace_id(), stream_id, e);
+                quic_conn.stream_shutdown(stream_id, quiche::Shutdown::Write, 0).ok();
+                resp.done = true;
+            }
+        }
+    }
+
+    let stream_ids = quic_conn.writable()
+        .filter(|stream_id| responses.get(stream_id).is_some_and(|r| r.body.is_some() && !r.done))
+        .collect::<Vec<u64>>();
+    for stream_id in stream_ids {
+        let resp = responses.get_mut(&stream_id).unwrap();
+        let body = resp.body.as_ref().unwrap();
+        while !resp.done {
+            let len = min(body.len() - resp.written, scratch_buf.len());
+            let chunk = &mut scratch_buf[..len];
+            match body {
+                HqBody::Generated(_, content) => content.fill(resp.written, chunk),
+                HqBody::File(file, _) => if let Err(e) = file.read_exact_at(chunk, resp.written as u64) {
+                    error!("{} failed to read file for stream {}: {}", quic_conn.trace_id(), stream_id, e);
+                    quic_conn.stream_shutdown(stream_id, quiche::Shutdown::Write, 0).ok();
+                    resp.done = true;
+                    break;
+                },
+            }
+            let fin = resp.written + len == body.len();
+            match quic_conn.stream_send(stream_id, chunk, fin) {
+                Ok(written) => {
+                    resp.written += written;
+                    resp.done = fin && written == len;
+                    if written < len {
+                        break; // try again next time
+                    }
+                }
+                Err(quiche::Error::Done) => break,
+                Err(e) => {
+                    error!("{} failed to send on stream {}: {:?}", quic_conn.trace_id(), stream_id, e);
+                    resp.done = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    // keep the state until the request is complete, so late data is not taken as a new request
+    responses.retain(|stream_id, r| {
+        let complete = r.done && r.request_fin;
+        if complete && r.body.is_some() {
+            info!("{} hq stream {}: sent {} B", quic_conn.trace_id(), stream_id, r.written);
+        }
+        !complete
+    });
+}
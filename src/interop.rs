@@ -0,0 +1,177 @@
+use crate::args::{ClientArgs, ServerArgs};
+use crate::client::client_with_workload;
+use crate::server::server;
+use crate::workload::{Workload, WorkloadEntry};
+use clap::Parser;
+use log::{error, info};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Exit code for test cases that are not supported, as expected by the QUIC Interop Runner
+pub const EXIT_UNSUPPORTED: i32 = 127;
+
+/// Test cases supported as client.
+/// Retry is handled by quiche transparently.
+/// ChaCha20 needs a TLS 1.3 cipher suite preference and key updates an API to initiate them,
+/// neither of which quiche exposes to clients
+const CLIENT_TEST_CASES: &[&str] = &["handshake", "transfer", "multiplexing", "retry", "resumption", "zerortt", "http3"];
+/// Test cases supported as server.
+/// ChaCha20 and key updates are chosen and initiated by the client.
+/// Retry is not supported, the endpoint has no hook to answer an Initial with a Retry packet
+const SERVER_TEST_CASES: &[&str] = &["handshake", "transfer", "multiplexing", "resumption", "zerortt", "chacha20", "keyupdate", "http3"];
+
+/// Port the server listens on in the runner's network
+const SERVER_PORT: u16 = 443;
+
+#[derive(Parser)]
+struct InteropClientArgs {
+    #[command(flatten)]
+    args: ClientArgs,
+}
+
+#[derive(Parser)]
+struct InteropServerArgs {
+    #[command(flatten)]
+    args: ServerArgs,
+}
+
+/// Run the test case of the QUIC Interop Runner given by the environment variables
+/// `ROLE` (`client` or `server`) and `TESTCASE`.
+/// A client downloads the space separated urls in `REQUESTS` on a single connection to the `DOWNLOADS` directory.
+/// A server serves the files below `WWW`, with `cert.pem` and `priv.key` in `CERTS`.
+/// `SSLKEYLOGFILE` is honored as by the client and server.
+/// Return the exit code of the process
+pub fn interop() -> i32 {
+    let role = env::var("ROLE").unwrap_or_default();
+    let test_case = env::var("TESTCASE").unwrap_or_default();
+    let supported = match role.as_str() {
+        "client" => CLIENT_TEST_CASES,
+        "server" => SERVER_TEST_CASES,
+        _ => {
+            error!("invalid ROLE {:?}, expected client or server", role);
+            return 1;
+        }
+    };
+    if !supported.contains(&test_case.as_str()) {
+        info!("test case {:?} is not supported as {}", test_case, role);
+        return EXIT_UNSUPPORTED;
+    }
+    let protocol = match test_case.as_str() {
+        "http3" => "h3",
+        _ => "hq-interop",
+    };
+    info!("run test case {} as {} with {}", test_case, role, protocol);
+    match role.as_str() {
+        "client" => run_client(protocol, &test_case),
+        _ => run_server(protocol),
+    }
+}
+
+fn env_path(name: &str, default: &str) -> PathBuf {
+    env::var_os(name).map_or_else(|| PathBuf::from(default), PathBuf::from)
+}
+
+/// Download the urls of `REQUESTS`.
+/// To test resumption and 0-RTT the first url is downloaded on a connection of its own,
+/// the others on a second connection resuming its session
+fn run_client(protocol: &str, test_case: &str) -> i32 {
+    let requests = env::var("REQUESTS").unwrap_or_default();
+    let urls = match requests.split_whitespace().map(url::Url::parse).collect::<Result<Vec<_>, _>>() {
+        Ok(v) if !v.is_empty() => v,
+        Ok(_) => {
+            error!("no REQUESTS given");
+            return 1;
+        }
+        Err(e) => {
+            error!("invalid REQUESTS {:?}: {}", requests, e);
+            return 1;
+        }
+    };
+    let mut argv: Vec<OsString> = vec![
+        "client".into(),
+        urls[0].as_str().into(),
+        "--no-verify".into(),
+        "--protocol".into(),
+        protocol.into(),
+        "--output-dir".into(),
+        env_path("DOWNLOADS", "/downloads").into(),
+    ];
+    let resume = matches!(test_case, "resumption" | "zerortt");
+    let session_file = env::temp_dir().join(format!("quiche-perf-interop-{}.session", std::process::id()));
+    if resume {
+        argv.push("--session-file".into());
+        argv.push(session_file.clone().into());
+    }
+    if test_case == "zerortt" {
+        argv.push("--early-data".into());
+    }
+    let args = InteropClientArgs::parse_from(argv).args;
+    let batches = match resume {
+        true if urls.len() > 1 => vec![&urls[..1], &urls[1..]],
+        _ => vec![&urls[..]],
+    };
+    let mut succeeded = 0;
+    let mut resumed = true;
+    for (i, batch) in batches.iter().enumerate() {
+        let workload = Workload {
+            entries: batch.iter()
+                .map(|url| WorkloadEntry {
+                    conn: 0,
+                    method: "GET".to_string(),
+                    path: url[url::Position::BeforePath..].to_string(),
+                    body_size: 0,
+                    start_offset: Duration::ZERO,
+                    depends_on: None,
+                    priority: None,
+                })
+                .collect(),
+        };
+        let app_data = client_with_workload(&args, workload);
+        if i > 0 && !app_data.results.iter().all(|r| r.resumed) {
+            error!("the session was not resumed");
+            resumed = false;
+        }
+        if i > 0 && test_case == "zerortt" && !app_data.results.iter().all(|r| r.tls.early_data) {
+            error!("the early data was not accepted");
+            resumed = false;
+        }
+        succeeded += app_data.results.iter()
+            .filter(|r| r.status.is_none_or(|s| (200..300).contains(&s)))
+            .count();
+    }
+    if resume {
+        fs::remove_file(&session_file).ok();
+    }
+    let failed = urls.len() - succeeded;
+    if failed > 0 {
+        error!("{} of {} requests failed", failed, urls.len());
+        return 1;
+    }
+    if !resumed {
+        return 1;
+    }
+    0
+}
+
+fn run_server(protocol: &str) -> i32 {
+    let certs = env_path("CERTS", "/certs");
+    let argv: Vec<OsString> = vec![
+        "server".into(),
+        "--bind".into(),
+        format!("[::]:{}", SERVER_PORT).into(),
+        "--cert".into(),
+        certs.join("cert.pem").into(),
+        "--key".into(),
+        certs.join("priv.key").into(),
+        "--root".into(),
+        env_path("WWW", "/www").into(),
+        "--protocol".into(),
+        protocol.into(),
+        "--early-data".into(),
+    ];
+    server(&InteropServerArgs::parse_from(argv).args, None);
+    0
+}
@@ -8,8 +8,21 @@ use env_logger as _;
 pub mod server;
 pub mod client;
 pub mod args;
-mod cert;
+pub mod workload;
+pub mod priority;
+pub mod cert;
+mod content;
+mod files;
+mod mem;
+mod har;
 mod h3;
+pub mod dgram;
+pub mod masque;
+mod perf;
+mod hq;
+pub mod sockopt;
+mod wakeup;
+pub mod interop;
 
 /// No error. This is used when the connection or stream needs to be closed, but there is no error to signal.
 /// RFC 99114
@@ -1,6 +1,9 @@
 use clap::{Parser, Subcommand};
-use quiche_perf::args::{ClientArgs, ServerArgs};
+use quiche_perf::args::{ClientArgs, GenCertArgs, ServerArgs, UdpEchoArgs};
+use quiche_perf::cert::gen_cert;
 use quiche_perf::client::client;
+use quiche_perf::interop::interop;
+use quiche_perf::masque::udp_echo;
 use quiche_perf::server::server;
 
 
@@ -10,7 +13,13 @@ enum Commands {
     #[command(alias = "-c")]
     Client(ClientArgs),
     #[command(alias = "-s")]
-    Server(ServerArgs)
+    Server(ServerArgs),
+    /// Echo UDP datagrams, as target of CONNECT-UDP tunnels
+    UdpEcho(UdpEchoArgs),
+    /// Generate a self-signed certificate, its key and SPKI hash
+    GenCert(GenCertArgs),
+    /// Run the test case of the QUIC Interop Runner given by its environment variables
+    Interop,
 }
 
 #[derive(Parser)]
@@ -27,5 +36,8 @@ fn main() {
     match args.command {
         Commands::Client(args) => { client(&args); },
         Commands::Server(args) => server(&args, None),
+        Commands::UdpEcho(args) => udp_echo(&args),
+        Commands::GenCert(args) => gen_cert(&args),
+        Commands::Interop => std::process::exit(interop()),
     }
 }
@@ -0,0 +1,158 @@
+use crate::args::UdpEchoArgs;
+use crate::dgram;
+use crate::dgram::DgramStats;
+use log::info;
+use std::cmp::min;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Prefix of the request path of a CONNECT-UDP tunnel, RFC 9298
+pub const CONNECT_UDP_PATH_PREFIX: &str = "/.well-known/masque/udp/";
+/// Value of the `:protocol` pseudo-header of a CONNECT-UDP request
+pub const CONNECT_UDP_PROTOCOL: &str = "connect-udp";
+/// Time to wait for outstanding datagrams after the last one was sent
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Request path of a tunnel to `host` and `port`, following the default URI template of RFC 9298
+pub fn connect_udp_path(host: &str, port: u16) -> String {
+    format!("{}{}/{}/", CONNECT_UDP_PATH_PREFIX, host.replace(':', "%3A"), port)
+}
+
+/// Parse the target host and port of a tunnel from the request path
+pub fn parse_connect_udp_path(path: &str) -> Option<(String, u16)> {
+    let rest = path.strip_prefix(CONNECT_UDP_PATH_PREFIX)?;
+    let rest = rest.strip_suffix('/').unwrap_or(rest);
+    let (host, port) = rest.split_once('/')?;
+    let host = host.replace("%3A", ":").replace("%3a", ":");
+    if host.is_empty() {
+        return None;
+    }
+    Some((host, port.parse().ok()?))
+}
+
+/// Append a QUIC variable-length integer, RFC 9000 section 16
+fn write_varint(v: u64, buf: &mut Vec<u8>) {
+    match v {
+        0..=0x3f => buf.push(v as u8),
+        0x40..=0x3fff => buf.extend_from_slice(&(v as u16 | 0x4000).to_be_bytes()),
+        0x4000..=0x3fff_ffff => buf.extend_from_slice(&(v as u32 | 0x8000_0000).to_be_bytes()),
+        _ => buf.extend_from_slice(&(v | 0xc000_0000_0000_0000).to_be_bytes()),
+    }
+}
+
+/// Read a QUIC variable-length integer.
+/// Return the value and the number of bytes read
+fn read_varint(buf: &[u8]) -> Option<(u64, usize)> {
+    let first = *buf.first()?;
+    let len = 1 << (first >> 6);
+    let bytes = buf.get(..len)?;
+    let mut v = (first & 0x3f) as u64;
+    for b in &bytes[1..] {
+        v = (v << 8) | *b as u64;
+    }
+    Some((v, len))
+}
+
+/// The framing of an HTTP datagram, RFC 9297 section 2.1: the quarter stream id of the request
+pub fn http_dgram_prefix(stream_id: u64) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(8);
+    write_varint(stream_id / 4, &mut prefix);
+    prefix
+}
+
+/// Parse an HTTP datagram.
+/// Return the request stream id and the payload, none if it is malformed
+pub fn parse_http_dgram(buf: &[u8]) -> Option<(u64, &[u8])> {
+    let (quarter_stream_id, len) = read_varint(buf)?;
+    Some((quarter_stream_id * 4, &buf[len..]))
+}
+
+/// The framing of a UDP payload in an HTTP datagram, RFC 9297 and RFC 9298:
+/// the quarter stream id of the request and the context id 0
+pub fn udp_dgram_prefix(stream_id: u64) -> Vec<u8> {
+    let mut prefix = http_dgram_prefix(stream_id);
+    write_varint(0, &mut prefix);
+    prefix
+}
+
+/// Parse an HTTP datagram carrying a UDP payload.
+/// Return the request stream id and the UDP payload,
+/// none if it is malformed or carries another context
+pub fn parse_udp_dgram(buf: &[u8]) -> Option<(u64, &[u8])> {
+    let (stream_id, payload) = parse_http_dgram(buf)?;
+    let (context_id, context_len) = read_varint(payload)?;
+    if context_id != 0 {
+        return None;
+    }
+    Some((stream_id, &payload[context_len..]))
+}
+
+/// Run a UDP echo server, as local target of CONNECT-UDP tunnels
+pub fn udp_echo(args: &UdpEchoArgs) {
+    let socket = UdpSocket::bind(args.bind).unwrap();
+    info!("UDP echo listening on {}", socket.local_addr().unwrap());
+    let mut buf = [0; 65_536];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).unwrap();
+        socket.send_to(&buf[..len], peer).ok();
+    }
+}
+
+/// Send the test datagrams directly to `target` and receive them echoed,
+/// as baseline for a tunnel
+pub fn direct_udp_test(target: SocketAddr, total: usize, size: usize, rate: Option<u64>) -> DgramStats {
+    let bind: SocketAddr = match target {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+        SocketAddr::V6(_) => "[::]:0".parse().unwrap(),
+    };
+    let socket = UdpSocket::bind(bind).unwrap();
+    socket.connect(target).unwrap();
+    socket.set_nonblocking(true).unwrap();
+
+    let mut buf = vec![0; size.max(dgram::HEADER_LEN)];
+    let mut recv_buf = [0; 65_536];
+    let start_instant = Instant::now();
+    let mut stats = DgramStats::new(start_instant);
+    let mut sent = 0;
+    let mut seq = 0;
+    let mut last_send_instant = start_instant;
+    loop {
+        let now = Instant::now();
+        let due = rate.is_none_or(|rate| sent < (rate as f64 * (now - start_instant).as_secs_f64()) as usize);
+        if sent < total && due {
+            let len = min(buf.len(), total - sent).max(dgram::HEADER_LEN);
+            dgram::write_header(&mut buf[..len], 0, seq, now - start_instant);
+            match socket.send(&buf[..len]) {
+                Ok(_) => {
+                    sent += len;
+                    seq += 1;
+                    last_send_instant = now;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => panic!("failed to send to {}: {}", target, e),
+            }
+        }
+        loop {
+            match socket.recv(&mut recv_buf) {
+                Ok(len) => {
+                    let now = Instant::now();
+                    if let Some((_, seq, send_time)) = dgram::read_header(&recv_buf[..len]) {
+                        if stats.received == 0 {
+                            stats = DgramStats::new(now);
+                        }
+                        stats.on_recv(len, seq, send_time, now - start_instant, now);
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // e.g. connection refused if nothing listens on the target
+                Err(e) => panic!("failed to receive from {}: {}", target, e),
+            }
+        }
+        if sent >= total && (stats.received == seq || now - last_send_instant > DRAIN_TIMEOUT) {
+            break;
+        }
+    }
+    stats.sent = Some(seq);
+    stats
+}
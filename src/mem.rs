@@ -0,0 +1,279 @@
+use crate::content::Content;
+use regex::Regex;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// Description of the generated resources, served in the body of 404 responses
+pub const GRAMMAR: &str = "\
+generated resources:
+  /<content>/<size>[?<option>[&<option>]...]
+  e.g. /mem/1MB, /mem/1.5GiB?chunk=16KiB or /rand/1GB?seed=42
+content:
+  mem      zero bytes
+  pattern  bytes 0 to 255, repeating
+  rand     pseudo-random bytes
+size:
+  <number>[<unit>]
+  number   decimal number like 10 or 2.5, must result in whole bytes
+  unit     B, kB, MB, GB, TB (powers of 1000) or KiB, MiB, GiB, TiB (powers of 1024), case insensitive
+options:
+  content=<zeros|pattern|rand>  content of the body, overrides the path
+  seed=<integer>                seed of the rand content, default 0
+  chunk=<size>                  max bytes written per DATA frame, at most the server's write buffer
+  rate=<rate>                   pace the body at this rate, e.g. 50Mbps
+  delay=<duration>              delay the headers, e.g. 200ms
+  ttfb=<duration>               delay the first body byte, counted from the request
+  chunk-interval=<duration>     send one chunk per interval
+rate:
+  <number><unit>, unit bps, kbps, Mbps or Gbps
+duration:
+  <number><unit>, unit us, ms or s
+";
+
+static PATH_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^/(mem|pattern|rand)/([^/]+)$").unwrap());
+static SIZE_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^([0-9]+)(?:\.([0-9]+))?([a-zA-Z]*)$").unwrap());
+static NUMBER_UNIT_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^([0-9]+(?:\.[0-9]+)?)([a-zA-Z]+)$").unwrap());
+
+/// Represents a request path of a generated resource, see [`GRAMMAR`]
+pub struct MemRequest {
+    pub size: usize,
+    pub content: Content,
+    /// max bytes written per DATA frame
+    pub chunk_size: Option<usize>,
+    pub throttle: Throttle,
+}
+
+/// Shapes when the response is sent, to emulate an application-limited sender
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Throttle {
+    /// delay of the headers
+    pub delay: Duration,
+    /// delay of the first body byte, counted from the request
+    pub ttfb: Duration,
+    /// body rate in bytes per second
+    pub rate: Option<u64>,
+    /// send one chunk per interval
+    pub chunk_interval: Option<Duration>,
+}
+
+impl Throttle {
+    pub fn is_throttled(&self) -> bool {
+        !self.delay.is_zero() || !self.ttfb.is_zero() || self.rate.is_some() || self.chunk_interval.is_some()
+    }
+}
+
+#[derive(Debug)]
+pub enum MemRequestError {
+    /// not a generated resource
+    NotFound,
+    /// a generated resource with invalid size or options
+    BadRequest(String),
+}
+
+impl FromStr for MemRequest {
+    type Err = MemRequestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, query) = s.split_once('?').unwrap_or((s, ""));
+        let c = PATH_REGEX.captures(path).ok_or(MemRequestError::NotFound)?;
+        let bad_request = MemRequestError::BadRequest;
+
+        let mut request = MemRequest {
+            size: parse_size(c.get(2).unwrap().as_str()).map_err(bad_request)?,
+            content: match c.get(1).unwrap().as_str() {
+                "mem" => Content::Zeros,
+                "pattern" => Content::Pattern,
+                "rand" => Content::Random(0),
+                _ => unreachable!(),
+            },
+            chunk_size: None,
+            throttle: Throttle::default(),
+        };
+
+        let mut seed = 0;
+        for option in query.split('&').filter(|o| !o.is_empty()) {
+            let (key, value) = option.split_once('=')
+                .ok_or_else(|| bad_request(format!("expected <key>=<value>: {}", option)))?;
+            match key {
+                "content" => {
+                    request.content = match value {
+                        "zeros" => Content::Zeros,
+                        "pattern" => Content::Pattern,
+                        "rand" => Content::Random(0),
+                        _ => return Err(bad_request(format!("unknown content: {}", value))),
+                    }
+                }
+                "seed" => {
+                    seed = value.parse::<u64>()
+                        .map_err(|e| bad_request(format!("invalid seed: {}", e)))?;
+                }
+                "chunk" => {
+                    let chunk_size = parse_size(value).map_err(bad_request)?;
+                    if chunk_size == 0 {
+                        return Err(bad_request("chunk size must not be 0".to_string()));
+                    }
+                    request.chunk_size = Some(chunk_size);
+                }
+                "rate" => {
+                    let rate = parse_rate(value).map_err(bad_request)?;
+                    if rate == 0 {
+                        return Err(bad_request("rate must not be 0".to_string()));
+                    }
+                    request.throttle.rate = Some(rate);
+                }
+                "delay" => request.throttle.delay = parse_duration(value).map_err(bad_request)?,
+                "ttfb" => request.throttle.ttfb = parse_duration(value).map_err(bad_request)?,
+                "chunk-interval" => {
+                    let interval = parse_duration(value).map_err(bad_request)?;
+                    if interval.is_zero() {
+                        return Err(bad_request("chunk interval must not be 0".to_string()));
+                    }
+                    request.throttle.chunk_interval = Some(interval);
+                }
+                _ => return Err(bad_request(format!("unknown option: {}", key))),
+            }
+        }
+        if let Content::Random(_) = request.content {
+            request.content = Content::Random(seed);
+        }
+        Ok(request)
+    }
+}
+
+/// Parse a size like `10`, `1.5GB` or `1GiB` to bytes, see [`GRAMMAR`]
+pub fn parse_size(s: &str) -> Result<usize, String> {
+    let c = SIZE_REGEX.captures(s).ok_or_else(|| format!("invalid size: {}", s))?;
+    let overflow = || format!("size too large: {}", s);
+    let unit = c.get(3).unwrap().as_str().to_ascii_lowercase();
+    let multiplier: u128 = match unit.as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return Err(format!("unknown unit: {}", c.get(3).unwrap().as_str())),
+    };
+
+    let integer = c.get(1).unwrap().as_str().parse::<u128>().map_err(|_| overflow())?;
+    let mut bytes = integer.checked_mul(multiplier).ok_or_else(overflow)?;
+    if let Some(fraction) = c.get(2) {
+        let digits = fraction.as_str().trim_end_matches('0');
+        let denominator = u32::try_from(digits.len()).ok()
+            .and_then(|d| 10u128.checked_pow(d))
+            .ok_or_else(|| format!("too many decimal places: {}", s))?;
+        let numerator = if digits.is_empty() {
+            0
+        } else {
+            digits.parse::<u128>().map_err(|_| overflow())?
+                .checked_mul(multiplier)
+                .ok_or_else(overflow)?
+        };
+        if numerator % denominator != 0 {
+            return Err(format!("not a whole number of bytes: {}", s));
+        }
+        bytes = bytes.checked_add(numerator / denominator).ok_or_else(overflow)?;
+    }
+    usize::try_from(bytes).map_err(|_| overflow())
+}
+
+/// Parse a rate like `50Mbps` to bytes per second, see [`GRAMMAR`]
+pub fn parse_rate(s: &str) -> Result<u64, String> {
+    let c = NUMBER_UNIT_REGEX.captures(s).ok_or_else(|| format!("invalid rate: {}", s))?;
+    let number = c.get(1).unwrap().as_str().parse::<f64>().map_err(|e| e.to_string())?;
+    let bits_per_second = match c.get(2).unwrap().as_str() {
+        "bps" => number,
+        "kbps" => number * 1E3,
+        "Mbps" => number * 1E6,
+        "Gbps" => number * 1E9,
+        unit => return Err(format!("unknown unit: {}", unit)),
+    };
+    if bits_per_second / 8.0 >= u64::MAX as f64 {
+        return Err(format!("rate too large: {}", s));
+    }
+    Ok((bits_per_second / 8.0) as u64)
+}
+
+/// Parse a duration like `200ms`, see [`GRAMMAR`]
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let c = NUMBER_UNIT_REGEX.captures(s).ok_or_else(|| format!("invalid duration: {}", s))?;
+    let number = c.get(1).unwrap().as_str().parse::<f64>().map_err(|e| e.to_string())?;
+    let seconds = match c.get(2).unwrap().as_str() {
+        "us" => number / 1E6,
+        "ms" => number / 1E3,
+        "s" => number,
+        unit => return Err(format!("unknown unit: {}", unit)),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("duration too large: {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sizes() {
+        for (s, bytes) in [
+            ("0", 0),
+            ("10", 10),
+            ("10B", 10),
+            ("1kB", 1_000),
+            ("1KB", 1_000),
+            ("1.5MB", 1_500_000),
+            ("2.50gb", 2_500_000_000),
+            ("1KiB", 1_024),
+            ("1.5MiB", 1_572_864),
+            ("0.5kib", 512),
+            ("1TiB", 1 << 40),
+        ] {
+            assert_eq!(parse_size(s), Ok(bytes), "{}", s);
+        }
+    }
+
+    #[test]
+    fn reject_invalid_sizes() {
+        for (s, e) in [
+            ("", "invalid size"),
+            ("-1", "invalid size"),
+            ("1.", "invalid size"),
+            ("1 MB", "invalid size"),
+            ("1XB", "unknown unit"),
+            ("1.5B", "not a whole number of bytes"),
+            ("0.0001kB", "not a whole number of bytes"),
+            ("1.0000000000000000000000000000000000000001", "too many decimal places"),
+            ("18446744073709551616", "size too large"),
+            ("18446744073709552TB", "size too large"),
+            ("99999999999999999999999999999999999999999", "size too large"),
+        ] {
+            let err = parse_size(s).unwrap_err();
+            assert!(err.starts_with(e), "{:?}: {}", s, err);
+        }
+    }
+
+    #[test]
+    fn parse_rates() {
+        assert_eq!(parse_rate("8bps"), Ok(1));
+        assert_eq!(parse_rate("50Mbps"), Ok(6_250_000));
+        assert_eq!(parse_rate("2.5Gbps"), Ok(312_500_000));
+        assert_eq!(parse_rate("0.8kbps"), Ok(100));
+        assert!(parse_rate("50").unwrap_err().starts_with("invalid rate"));
+        assert!(parse_rate("50mbps").unwrap_err().starts_with("unknown unit"));
+        assert!(parse_rate("1000000000000000000000000Gbps").unwrap_err().starts_with("rate too large"));
+    }
+
+    #[test]
+    fn parse_request_options() {
+        let r = MemRequest::from_str("/rand/1.5KiB?seed=7&chunk=1kB&rate=8Mbps&delay=200ms").unwrap();
+        assert_eq!(r.size, 1_536);
+        assert_eq!(r.content, Content::Random(7));
+        assert_eq!(r.chunk_size, Some(1_000));
+        assert_eq!(r.throttle.rate, Some(1_000_000));
+        assert_eq!(r.throttle.delay, Duration::from_millis(200));
+        assert!(matches!(MemRequest::from_str("/mem/1MB?chunk=0"), Err(MemRequestError::BadRequest(_))));
+        assert!(matches!(MemRequest::from_str("/file.txt"), Err(MemRequestError::NotFound)));
+    }
+}
@@ -0,0 +1,91 @@
+use log::{debug, error, info};
+use quiche_mio_runner::quiche_endpoint::quiche;
+use std::cmp::min;
+use std::collections::HashMap;
+
+/// ALPN of the QUIC performance protocol, draft-banks-quic-performance
+pub const APPLICATION_PROTOCOL: &[&[u8]] = &[b"perf"];
+
+/// Length of the request header: the number of bytes to send back, 64 bit big endian.
+/// The rest of the request stream is uploaded data
+pub const REQUEST_LEN: usize = 8;
+
+/// A response of the perf server, keyed by the stream id
+#[derive(Default)]
+pub struct PerfResponse {
+    request: [u8; REQUEST_LEN],
+    request_read: usize,
+    uploaded: u64,
+    upload_complete: bool,
+    written: u64,
+    fin_sent: bool,
+}
+
+/// Read the requests and discard the uploaded data,
+/// then send the requested number of bytes on each stream
+pub fn handle_server_streams(quic_conn: &mut quiche::Connection, responses: &mut HashMap<u64, PerfResponse>, buf: &mut [u8], zeros: &[u8]) {
+    for stream_id in quic_conn.readable().collect::<Vec<u64>>() {
+        let resp = responses.entry(stream_id).or_default();
+        loop {
+            match quic_conn.stream_recv(stream_id, buf) {
+                Ok((len, fin)) => {
+                    let header_len = min(REQUEST_LEN - resp.request_read, len);
+                    resp.request[resp.request_read..resp.request_read + header_len].copy_from_slice(&buf[..header_len]);
+                    resp.request_read += header_len;
+                    resp.uploaded += (len - header_len) as u64;
+                    if fin {
+                        resp.upload_complete = true;
+                        break;
+                    }
+                }
+                Err(quiche::Error::Done) => break,
+                Err(e) => {
+                    error!("{} failed to receive on stream {}: {:?}", quic_conn.trace_id(), stream_id, e);
+                    break;
+                }
+            }
+        }
+        if resp.upload_complete && resp.request_read < REQUEST_LEN {
+            debug!("{} incomplete request on stream {}", quic_conn.trace_id(), stream_id);
+            responses.remove(&stream_id);
+            quic_conn.stream_shutdown(stream_id, quiche::Shutdown::Write, 0).ok();
+        }
+    }
+
+    let stream_ids = quic_conn.writable()
+        .filter(|stream_id| responses.get(stream_id).is_some_and(|r| r.request_read == REQUEST_LEN && !r.fin_sent))
+        .collect::<Vec<u64>>();
+    for stream_id in stream_ids {
+        let resp = responses.get_mut(&stream_id).unwrap();
+        let requested = u64::from_be_bytes(resp.request);
+        while !resp.fin_sent {
+            let remaining = requested - resp.written;
+            let len = min(remaining, zeros.len() as u64) as usize;
+            let fin = len as u64 == remaining;
+            match quic_conn.stream_send(stream_id, &zeros[..len], fin) {
+                Ok(written) => {
+                    resp.written += written as u64;
+                    resp.fin_sent = fin && written == len;
+                    if written < len {
+                        break; // try again next time
+                    }
+                }
+                Err(quiche::Error::Done) => break,
+                Err(e) => {
+                    error!("{} failed to send on stream {}: {:?}", quic_conn.trace_id(), stream_id, e);
+                    responses.remove(&stream_id);
+                    break;
+                }
+            }
+        }
+    }
+
+    // keep the state until the upload is complete, so late data is not taken as a new request
+    responses.retain(|stream_id, r| {
+        let complete = r.fin_sent && r.upload_complete;
+        if complete {
+            info!("{} perf stream {}: sent {} B, received {} B", quic_conn.trace_id(), stream_id, r.written, r.uploaded);
+        }
+        !complete
+    });
+}
@@ -0,0 +1,110 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Extensible priority of an HTTP request, RFC 9218
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Priority {
+    /// 0 is the most urgent, 7 the least urgent
+    pub urgency: u8,
+    /// whether the response can be processed incrementally,
+    /// so it can be interleaved with other responses of the same urgency
+    pub incremental: bool,
+}
+
+impl Priority {
+    pub const DEFAULT_URGENCY: u8 = 3;
+    pub const MAX_URGENCY: u8 = 7;
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Self {
+            urgency: Self::DEFAULT_URGENCY,
+            incremental: false,
+        }
+    }
+}
+
+/// Parse the structured field value of a `priority` header or PRIORITY_UPDATE frame,
+/// e.g. `u=1, i`.
+/// Unknown parameters are ignored, as required by RFC 9218
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut p = Priority::default();
+        for param in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, "?1"));
+            match key {
+                "u" => {
+                    p.urgency = value.parse::<u8>()
+                        .ok()
+                        .filter(|u| *u <= Self::MAX_URGENCY)
+                        .ok_or_else(|| format!("invalid urgency: {}", value))?;
+                }
+                "i" => {
+                    p.incremental = match value {
+                        "?1" => true,
+                        "?0" => false,
+                        _ => return Err(format!("invalid incremental: {}", value)),
+                    };
+                }
+                _ => {}
+            }
+        }
+        Ok(p)
+    }
+}
+
+impl Display for Priority {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "u={}", self.urgency)?;
+        if self.incremental {
+            write!(f, ", i")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_priorities() {
+        for (s, urgency, incremental) in [
+            ("", 3, false),
+            ("u=0", 0, false),
+            ("u=7, i", 7, true),
+            ("i, u=1", 1, true),
+            ("u=2,i=?0", 2, false),
+            ("u=5, i=?1, x=y", 5, true),
+        ] {
+            assert_eq!(Priority::from_str(s), Ok(Priority { urgency, incremental }), "{}", s);
+        }
+    }
+
+    #[test]
+    fn reject_invalid_priorities() {
+        for (s, e) in [
+            ("u=8", "invalid urgency"),
+            ("u=-1", "invalid urgency"),
+            ("u", "invalid urgency"),
+            ("i=1", "invalid incremental"),
+            ("i=?2", "invalid incremental"),
+        ] {
+            let err = Priority::from_str(s).unwrap_err();
+            assert!(err.starts_with(e), "{:?}: {}", s, err);
+        }
+    }
+
+    #[test]
+    fn priorities_round_trip() {
+        for urgency in 0..=Priority::MAX_URGENCY {
+            for incremental in [false, true] {
+                let p = Priority { urgency, incremental };
+                assert_eq!(Priority::from_str(&p.to_string()), Ok(p));
+            }
+        }
+    }
+}
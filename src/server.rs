@@ -1,33 +1,92 @@
-use crate::args::ServerArgs;
-use crate::server::ResponseBody::{Static, Zeros};
+use crate::args::{Protocol, ServerArgs};
+use crate::server::ResponseBody::{Echo, Generated, Owned, Tunnel};
+use crate::server::ResponseBody::File as FileBody;
 use boring::ssl::{SslContextBuilder, SslMethod};
+use boring::pkey::{PKey, Private};
+use boring::x509::X509;
 use log::{debug, error, info};
 use mio::unix::pipe::Receiver;
 use quiche_mio_runner as runner;
 use quiche_mio_runner::quiche_endpoint::quiche::h3::NameValue;
-use quiche_mio_runner::quiche_endpoint::quiche::{h3, PathStats, PROTOCOL_VERSION};
+use quiche_mio_runner::quiche_endpoint::quiche::{h3, PathStats};
 use quiche_mio_runner::quiche_endpoint::ServerConfig;
 use quiche_mio_runner::quiche_endpoint::{quiche, Conn, MAX_UDP_PAYLOAD};
 use quiche_mio_runner::quiche_endpoint::{Endpoint, EndpointConfig};
 use quiche_mio_runner::Socket;
-use regex::Regex;
 use std::collections::HashMap;
+use std::cmp::{max, min};
+use std::fs::File;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use std::str::{from_utf8, FromStr};
-use crate::cert::load_or_generate_keys;
+use crate::cert::{load_keys, load_or_generate_keys, log_handshake_params, set_alpn, set_cert_store, set_client_auth, set_groups, set_keylog_from_env, subject_to_string, tls_alert_description, CertStore};
+use crate::content::Content;
+use crate::files::{content_type, open_file, Roots};
+use crate::h3::{error_hint, field_section_size, filler_headers, h3_config, hdrs_to_strings, log_peer_settings};
+use crate::dgram;
+use crate::dgram::DgramSender;
+use crate::hq;
+use crate::hq::HqResponse;
+use crate::perf;
+use crate::perf::PerfResponse;
+use crate::masque::{connect_udp_path, http_dgram_prefix, parse_connect_udp_path, parse_udp_dgram, udp_dgram_prefix, CONNECT_UDP_PROTOCOL};
+use crate::mem::{parse_rate, parse_size, MemRequest, MemRequestError, Throttle, GRAMMAR};
+use crate::priority::Priority;
+use crate::sockopt::configure_socket;
+use crate::wakeup::{Wakeup, Watch};
 
 const H3_BUF_SIZE: usize = MAX_UDP_PAYLOAD * 8;
+/// bytes a rate-limited response may send ahead of its rate
+const RATE_BURST: usize = 16_384;
+/// Max total size of the filler headers of `/hdrs/<size>`
+const MAX_HDRS_SIZE: usize = 1 << 20;
+/// Max number of filler headers of `/hdrs/<size>`
+const MAX_HDRS_COUNT: usize = 10_000;
+/// Max request bytes of `/echo` waiting to be sent, the server stops reading the request beyond
+const MAX_ECHO_PENDING: usize = H3_BUF_SIZE;
+
+/// Description of the test endpoints, served in the body of 404 responses
+const ENDPOINTS: &str = "\
+test endpoints:
+  /echo                          respond with the request body
+  /headers                       respond with the request headers as JSON
+  /status/<code>                 respond with the status code, 200 to 599
+  /trailers                      respond with trailers after the body
+  /hdrs/<size>[?count=<count>]   respond with filler headers of this total size,
+                                 split into count headers or about 1 KiB each,
+                                 at most 1 MiB in at most 10000 headers
+  CONNECT-UDP to /.well-known/masque/udp/<host>/<port>/, with --connect-udp
+  /dgram/<size>[?<option>...]    send QUIC datagrams with this total payload size,
+                                 then respond with the number of sent datagrams as dgram-sent trailer
+                                 options: size=<size> per datagram, rate=<rate> e.g. 50Mbps
+";
 
 type Runner = runner::Runner<ConnAppData, AppData<H3_BUF_SIZE>, ()>;
 
 struct AppData<const BUF_SIZE: usize> {
     h3_config: h3::Config,
+    /// zero bytes
     h3_buf: [u8; BUF_SIZE],
+    /// for generating non-zero content and reading files
+    h3_scratch_buf: [u8; BUF_SIZE],
+    /// canonical directories to serve files from
+    roots: Roots,
+    /// act as CONNECT-UDP proxy
+    connect_udp: bool,
+    protocol: Protocol,
+    /// wakes the runner when throttled responses are due and tunnel targets send
+    wakeup: Wakeup,
 }
 
 #[derive(Default)]
 struct ConnAppData {
     h3_conn: Option<h3::Connection>,
     partial_responses: HashMap<u64, PartialResponse>,
+    perf_responses: HashMap<u64, PerfResponse>,
+    hq_responses: HashMap<u64, HqResponse>,
+    peer_settings_logged: bool,
 }
 
 struct PartialResponse {
@@ -35,43 +94,153 @@ struct PartialResponse {
     // some if send pending
     headers: Option<Vec<h3::Header>>,
     body: ResponseBody,
+    priority: Priority,
+    /// max bytes written per DATA frame, limited by the buffer size
+    chunk_size: Option<usize>,
+    request_instant: Instant,
+    throttle: Throttle,
+    /// some if sent after the body
+    trailers: Option<Vec<h3::Header>>,
+    /// some if datagrams are sent before the body
+    dgrams: Option<DgramSender>,
+    /// when a throttled response may send next, if it had to wait
+    next_send_instant: Option<Instant>,
+    /// watches the socket of a tunnel while it is open
+    watch: Option<Watch>,
+}
+
+impl PartialResponse {
+    fn new(status: u16, mut headers: Vec<h3::Header>, body: ResponseBody, priority: Priority) -> Self {
+        headers.insert(0, h3::Header::new(b":status", status.to_string().as_bytes()));
+        headers.insert(1, h3::Header::new(b"server", b"quiche"));
+        // a 204 response must not have a content-length
+        if let Some(len) = body.len().filter(|_| status != 204) {
+            headers.insert(2, h3::Header::new(b"content-length", len.to_string().as_bytes()));
+        }
+        PartialResponse {
+            written: 0,
+            headers: Some(headers),
+            body,
+            priority,
+            chunk_size: None,
+            request_instant: Instant::now(),
+            throttle: Throttle::default(),
+            trailers: None,
+            dgrams: None,
+            next_send_instant: None,
+            watch: None,
+        }
+    }
 }
 
 enum ResponseBody {
-    /// body with static content
-    Static(&'static [u8]),
-    /// body with zero bytes of specified length, served from memory
-    Zeros(usize),
+    /// body with content in memory
+    Owned(Vec<u8>),
+    /// body with generated content of specified length, served from memory
+    Generated(usize, Content),
+    /// body with file content of specified length, read chunk by chunk
+    File(File, usize),
+    /// the request body, sent back as it arrives
+    Echo(EchoBody),
+    /// no body, the stream stays open while UDP payloads are proxied to the socket as datagrams
+    Tunnel(UdpSocket),
+}
+
+#[derive(Default)]
+struct EchoBody {
+    /// received but not yet sent
+    pending: Vec<u8>,
+    received: usize,
+    /// whether the request body is complete
+    fin: bool,
+    /// whether reading the request body stopped at [`MAX_ECHO_PENDING`]
+    paused: bool,
 }
 
 impl ResponseBody {
-    fn len(&self) -> usize {
+    /// None if not yet known
+    fn len(&self) -> Option<usize> {
         match self {
-            Static(v) => v.len(),
-            Zeros(l) => *l
+            Owned(v) => Some(v.len()),
+            Generated(l, _) => Some(*l),
+            FileBody(_, l) => Some(*l),
+            Echo(e) => e.fin.then_some(e.received),
+            Tunnel(_) => None,
         }
     }
 }
 
+/// The TLS context of a certificate with the TLS options of the server
+fn ssl_context_builder(cert: &X509, key: &PKey<Private>, groups: Option<&str>, client_ca: Option<&Path>, require_client_cert: bool) -> SslContextBuilder {
+    let mut b = SslContextBuilder::new(SslMethod::tls()).unwrap();
+    b.set_private_key(key).unwrap();
+    b.set_certificate(cert).unwrap();
+    set_keylog_from_env(&mut b);
+    set_groups(&mut b, groups);
+    log_handshake_params(&mut b);
+    if let Some(ca) = client_ca {
+        set_client_auth(&mut b, ca, require_client_cert);
+    }
+    b
+}
+
 #[allow(clippy::field_reassign_with_default)]
 pub fn server(args: &ServerArgs, close_pipe_rx: Option<&mut Receiver>) {
-    let (cert, key) = load_or_generate_keys(&args.cert, &args.key);
+    if args.cert.len() != args.key.len() {
+        panic!("provide a key for each certificate");
+    }
+    let (cert, key) = load_or_generate_keys(
+        args.cert.first().map(PathBuf::as_path),
+        args.key.first().map(PathBuf::as_path),
+        &args.cert_gen,
+        args.write_generated_cert.as_deref(),
+    );
+    let certs = std::iter::once((cert, key))
+        .chain(args.cert.iter().zip(&args.key).skip(1).map(|(cert, key)| load_keys(cert, key)))
+        .collect::<Vec<_>>();
+    let cert_store = CertStore::new(
+        args.cert.iter().cloned().zip(args.key.iter().cloned()).collect(),
+        &certs,
+        Box::new({
+            let groups = args.groups.clone();
+            let client_ca = args.client_ca.clone();
+            let require_client_cert = args.require_client_cert;
+            let protos = args.protocol.application_protos();
+            move |cert: &X509, key: &PKey<Private>| {
+                let mut b = ssl_context_builder(cert, key, groups.as_deref(), client_ca.as_deref(), require_client_cert);
+                set_alpn(&mut b, protos);
+                b.build()
+            }
+        }),
+    );
+    let canonical = |root: &PathBuf| {
+        root.canonicalize().unwrap_or_else(|e| panic!("invalid root directory {:?}: {}", root, e))
+    };
+    let roots = Roots {
+        default: args.root.as_ref().map(canonical),
+        hosts: args.vhost.iter().map(|v| (v.host.clone(), canonical(&v.root))).collect(),
+    };
 
     let socket = Socket::bind(args.bind, args.disable_gro, false, args.disable_gso).unwrap();
     assert_eq!(socket.enable_gro, !args.disable_gro);
     assert!(socket.enable_pacing);
     assert_eq!(socket.enable_gso, !args.disable_gso);
     let local_addr = socket.local_addr;
-    info!("Server listening on https://{}", local_addr);
+    match args.protocol {
+        Protocol::H3 => info!("Server listening on https://{}", local_addr),
+        Protocol::Perf => info!("Server listening on {} with the perf protocol", local_addr),
+        Protocol::Hq => info!("Server listening on {} with hq-interop", local_addr),
+    }
+    info!("socket: {}", configure_socket(&socket.sock, local_addr, &args.socket));
 
     let client_config = {
-        let mut c = quiche::Config::with_boring_ssl_ctx_builder(PROTOCOL_VERSION, {
-            let mut b = SslContextBuilder::new(SslMethod::tls()).unwrap();
-            b.set_private_key(&key).unwrap();
-            b.set_certificate(&cert).unwrap();
+        let mut c = quiche::Config::with_boring_ssl_ctx_builder(args.quic_version, {
+            let (cert, key) = &certs[0];
+            let mut b = ssl_context_builder(cert, key, args.groups.as_deref(), args.client_ca.as_deref(), args.require_client_cert);
+            set_cert_store(&mut b, cert_store);
             b
         }).unwrap();
-        c.set_application_protos(quiche::h3::APPLICATION_PROTOCOL).unwrap();
+        c.set_application_protos(args.protocol.application_protos()).unwrap();
         c.set_max_idle_timeout(args.idle_timeout);
         c.set_initial_max_streams_bidi(args.max_streams_bidi);
         c.set_initial_max_streams_uni(args.max_streams_uni);
@@ -86,6 +255,10 @@ pub fn server(args: &ServerArgs, close_pipe_rx: Option<&mut Receiver>) {
         c.set_max_stream_window(16_777_216);
         c.enable_pacing(true);
         c.grease(false);
+        c.enable_dgram(true, dgram::RECV_QUEUE_LEN, dgram::SEND_QUEUE_LEN);
+        if args.early_data {
+            c.enable_early_data();
+        }
         c
     };
 
@@ -103,8 +276,19 @@ pub fn server(args: &ServerArgs, close_pipe_rx: Option<&mut Receiver>) {
             c
         },
         AppData {
-            h3_config: h3::Config::new().unwrap(),
+            h3_config: {
+                let mut c = h3_config(&args.h3);
+                if args.connect_udp {
+                    c.enable_extended_connect(true);
+                }
+                c
+            },
             h3_buf: [0; H3_BUF_SIZE],
+            h3_scratch_buf: [0; H3_BUF_SIZE],
+            roots,
+            connect_udp: args.connect_udp,
+            protocol: args.protocol,
+            wakeup: Wakeup::new(local_addr),
         },
     );
 
@@ -125,9 +309,23 @@ pub fn server(args: &ServerArgs, close_pipe_rx: Option<&mut Receiver>) {
 }
 
 fn on_close(c: &Conn<ConnAppData>, _: &mut AppData<H3_BUF_SIZE>) {
+    for (side, err) in [("client", c.conn.peer_error()), ("server", c.conn.local_error())] {
+        if let Some(err) = err
+            && !err.is_app
+            && let Some(alert) = tls_alert_description(err.error_code) {
+            error!("{} TLS alert {} sent by the {}", c.conn.trace_id(), alert, side);
+        }
+    }
+    let sni = c.conn.server_name().map(|name| format!(" sni {}", name)).unwrap_or_default();
+    let client = c.conn.peer_cert()
+        .and_then(|der| X509::from_der(der).ok())
+        .map(|cert| format!(" client {:?}", subject_to_string(&cert)))
+        .unwrap_or_default();
     info!(
-        "{} connection collected {:?} {:?}",
+        "{} connection collected{}{} {:?} {:?}",
         c.conn.trace_id(),
+        sni,
+        client,
         c.conn.stats(),
         c.conn.path_stats().collect::<Vec<PathStats>>()
     );
@@ -136,6 +334,27 @@ fn on_close(c: &Conn<ConnAppData>, _: &mut AppData<H3_BUF_SIZE>) {
 fn post_handle_recvs(runner: &mut Runner) {
     let endpoint = &mut runner.endpoint;
     for i in endpoint.conn_index_iter() {
+        let (conn, app_data) = endpoint.conn_with_app_data_mut(i);
+        if app_data.protocol != Protocol::H3 {
+            let Some(conn) = conn else {
+                continue
+            };
+            if !conn.conn.is_established() && !conn.conn.is_in_early_data() {
+                continue; // not ready yet
+            }
+            match app_data.protocol {
+                Protocol::Perf => perf::handle_server_streams(&mut conn.conn, &mut conn.app_data.perf_responses, &mut runner.buf, &app_data.h3_buf),
+                Protocol::Hq => hq::handle_server_streams(
+                    &mut conn.conn,
+                    &mut conn.app_data.hq_responses,
+                    &mut runner.buf,
+                    &mut app_data.h3_scratch_buf,
+                    &app_data.roots,
+                ),
+                Protocol::H3 => unreachable!(),
+            }
+            continue;
+        }
         let Some(conn) = endpoint.conn(i) else {
             continue
         };
@@ -160,42 +379,94 @@ fn post_handle_recvs(runner: &mut Runner) {
         let h3 = conn.app_data.h3_conn.as_mut().unwrap();
         let partial_responses = &mut conn.app_data.partial_responses;
 
-        handle_h3_requests(h3, quic, partial_responses).expect("TODO: panic message");
+        if handle_h3_requests(h3, quic, partial_responses, &mut runner.buf, &app_data.roots, app_data.connect_udp).is_err() {
+            continue; // already logged
+        }
+        if !conn.app_data.peer_settings_logged {
+            conn.app_data.peer_settings_logged = log_peer_settings(quic.trace_id(), h3);
+        }
+
+        // echo requests are read again once their pending bytes went out
+        let paused = partial_responses.iter()
+            .filter(|(_, r)| matches!(&r.body, Echo(e) if e.paused))
+            .map(|(stream_id, _)| *stream_id)
+            .collect::<Vec<u64>>();
+        for stream_id in paused {
+            recv_request_body(h3, quic, partial_responses, stream_id, &mut runner.buf);
+        }
+        handle_h3_writable(quic, h3, partial_responses, &app_data.h3_buf, &mut app_data.h3_scratch_buf);
+        handle_tunnels(quic, partial_responses, &mut runner.buf, &app_data.wakeup);
 
-        handle_h3_writable(quic, h3, partial_responses, &app_data.h3_buf);
+        // responses waiting for flow control have no future send instant, they wait for the peer instead
+        let now = Instant::now();
+        if let Some(next) = partial_responses.values().filter_map(|r| r.next_send_instant).filter(|i| *i > now).min() {
+            app_data.wakeup.at(next);
+        }
     }
 }
 
-fn handle_h3_requests(h3_conn: &mut h3::Connection, quic_conn: &mut quiche::Connection, partial_responses: &mut HashMap<u64, PartialResponse>) -> h3::Result<()> {
+fn handle_h3_requests(h3_conn: &mut h3::Connection, quic_conn: &mut quiche::Connection, partial_responses: &mut HashMap<u64, PartialResponse>, buf: &mut [u8], roots: &Roots, connect_udp: bool) -> h3::Result<()> {
     loop {
         match h3_conn.poll(quic_conn) {
             Ok((stream_id, h3::Event::Headers { list, more_frames: _ })) => {
                 info!(
-                        "{} got request {:?} on stream id {}",
+                        "{} got request {:?} ({} B header section) on stream id {}",
                         quic_conn.trace_id(),
                         list,
+                        field_section_size(&list),
                         stream_id
                     );
-                let partial_response = build_h3_response(list.as_slice(), stream_id, quic_conn);
+                let mut partial_response = build_h3_response(list.as_slice(), stream_id, quic_conn, roots, connect_udp);
+                // a PRIORITY_UPDATE might have arrived before the request
+                if let Some(p) = take_priority_update(h3_conn, stream_id) {
+                    partial_response.priority = p;
+                }
+                let p = partial_response.priority;
+                quic_conn.stream_priority(stream_id, p.urgency, p.incremental).ok();
                 partial_responses.insert(stream_id, partial_response);
             }
-            Ok((_stream_id, h3::Event::Finished)) => (),
+            Ok((stream_id, h3::Event::Data)) => {
+                recv_request_body(h3_conn, quic_conn, partial_responses, stream_id, buf);
+            }
+            Ok((stream_id, h3::Event::Finished)) => {
+                if let Some(PartialResponse { body: Echo(e), .. }) = partial_responses.get_mut(&stream_id) {
+                    e.fin = true;
+                } else if let Some(PartialResponse { body: Tunnel(_), .. }) = partial_responses.get(&stream_id) {
+                    // the client closed the tunnel
+                    info!("{} tunnel on stream {} closed", quic_conn.trace_id(), stream_id);
+                    partial_responses.remove(&stream_id);
+                    h3_conn.send_body(quic_conn, stream_id, &[], true).ok();
+                }
+            }
             Ok((prioritized_element_id, h3::Event::PriorityUpdate)) => {
                 info!(
                     "{} PRIORITY_UPDATE triggered for element ID={}",
                     quic_conn.trace_id(),
                     prioritized_element_id
                 );
+                // the update is taken when the request arrives, if there is no response yet
+                if let Some(resp) = partial_responses.get_mut(&prioritized_element_id)
+                    && let Some(p) = take_priority_update(h3_conn, prioritized_element_id) {
+                    resp.priority = p;
+                    quic_conn.stream_priority(prioritized_element_id, p.urgency, p.incremental).ok();
+                }
             }
-            Ok((stream_id, e)) => {
-                info!("{:?} on stream {}", e, stream_id);
-                unimplemented!()
+            Ok((stream_id, h3::Event::Reset(e))) => {
+                info!("{} stream {} reset by the client with error code {}", quic_conn.trace_id(), stream_id, e);
+                // stop sending the response, a tunnel is closed along with its socket
+                if partial_responses.remove(&stream_id).is_some() {
+                    quic_conn.stream_shutdown(stream_id, quiche::Shutdown::Write, h3::WireErrorCode::RequestCancelled as u64).ok();
+                }
+            }
+            Ok((push_id, h3::Event::GoAway)) => {
+                // the server does not push, so there is nothing to cancel
+                info!("{} GOAWAY from the client with push ID {}", quic_conn.trace_id(), push_id);
             }
             Err(h3::Error::Done) => {
                 break;
             }
             Err(e) => {
-                error!("{} HTTP/3 error {:?}", quic_conn.trace_id(), e);
+                error!("{} HTTP/3 error {:?}{}", quic_conn.trace_id(), e, error_hint(&e));
                 return Err(e);
             }
         }
@@ -203,67 +474,265 @@ fn handle_h3_requests(h3_conn: &mut h3::Connection, quic_conn: &mut quiche::Conn
     Ok(())
 }
 
-fn handle_h3_writable(quic_conn: &mut quiche::Connection, h3_conn: &mut h3::Connection, partial_responses: &mut HashMap<u64, PartialResponse>, buf: &[u8]) {
-    'streamLoop: for stream_id in quic_conn.writable() {
-        let resp = match partial_responses.get_mut(&stream_id) {
-            None => continue, // no such key
-            Some(v) => v
+/// Read the request body, discarded unless echoed.
+/// An echoed body is read while less than [`MAX_ECHO_PENDING`] bytes wait to be sent,
+/// so flow control pushes back on a client that does not read the response
+fn recv_request_body(h3_conn: &mut h3::Connection, quic_conn: &mut quiche::Connection, partial_responses: &mut HashMap<u64, PartialResponse>, stream_id: u64, buf: &mut [u8]) {
+    let mut echo = match partial_responses.get_mut(&stream_id) {
+        Some(PartialResponse { body: Echo(e), .. }) => Some(e),
+        _ => None,
+    };
+    loop {
+        let len = match &mut echo {
+            Some(e) => {
+                e.paused = e.pending.len() >= MAX_ECHO_PENDING;
+                if e.paused {
+                    break;
+                }
+                min(buf.len(), MAX_ECHO_PENDING - e.pending.len())
+            }
+            None => buf.len(),
+        };
+        let Ok(read) = h3_conn.recv_body(quic_conn, stream_id, &mut buf[..len]) else {
+            break;
         };
+        debug!(
+            "{} got {} bytes of request data on stream {}",
+            quic_conn.trace_id(),
+            read,
+            stream_id
+        );
+        if let Some(e) = &mut echo {
+            e.pending.extend_from_slice(&buf[..read]);
+            e.received += read;
+        }
+    }
+}
 
-        if let Some(h) = &resp.headers {
-            match h3_conn.send_response(
-                quic_conn,
-                stream_id,
-                h,
-                false,
-            ) {
-                Ok(_) => {
-                    resp.headers = None;
-                }
-                Err(h3::Error::StreamBlocked) => continue 'streamLoop, // try again next time
-                Err(e) => {
-                    error!("{} error sending response {:?}", quic_conn.trace_id(), e);
-                    continue 'streamLoop;
-                }
+fn take_priority_update(h3_conn: &mut h3::Connection, stream_id: u64) -> Option<Priority> {
+    let value = h3_conn.take_last_priority_update(stream_id).ok()?;
+    match from_utf8(&value).map_err(|e| e.to_string()).and_then(Priority::from_str) {
+        Ok(p) => Some(p),
+        Err(e) => {
+            debug!("invalid PRIORITY_UPDATE for stream {}: {}", stream_id, e);
+            None
+        }
+    }
+}
+
+/// Send the pending responses in priority order.
+/// More urgent responses are served first.
+/// Non-incremental responses of the same urgency are served one after another in stream order,
+/// incremental ones are interleaved chunk by chunk.
+fn handle_h3_writable(quic_conn: &mut quiche::Connection, h3_conn: &mut h3::Connection, partial_responses: &mut HashMap<u64, PartialResponse>, buf: &[u8], scratch_buf: &mut [u8]) {
+    let mut stream_ids = quic_conn.writable()
+        .filter(|stream_id| partial_responses.contains_key(stream_id))
+        .collect::<Vec<u64>>();
+    stream_ids.sort_by_key(|stream_id| {
+        let p = partial_responses[stream_id].priority;
+        (p.urgency, p.incremental, *stream_id)
+    });
+
+    let mut i = 0;
+    while i < stream_ids.len() {
+        let p = partial_responses[&stream_ids[i]].priority;
+        if !p.incremental {
+            while send_response_chunk(quic_conn, h3_conn, partial_responses, stream_ids[i], buf, scratch_buf) {}
+            i += 1;
+            continue;
+        }
+        let group_len = stream_ids[i..].iter()
+            .take_while(|stream_id| partial_responses.get(stream_id).is_some_and(|r| r.priority == p))
+            .count();
+        let mut active = stream_ids[i..i + group_len].to_vec();
+        while !active.is_empty() {
+            active.retain(|stream_id| send_response_chunk(quic_conn, h3_conn, partial_responses, *stream_id, buf, scratch_buf));
+        }
+        i += group_len;
+    }
+}
+
+/// Send the pending headers and the next chunk of the body.
+/// Return true if the response is not yet complete and the stream is not blocked
+fn send_response_chunk(quic_conn: &mut quiche::Connection, h3_conn: &mut h3::Connection, partial_responses: &mut HashMap<u64, PartialResponse>, stream_id: u64, buf: &[u8], scratch_buf: &mut [u8]) -> bool {
+    let Some(resp) = partial_responses.get_mut(&stream_id) else {
+        return false;
+    };
+    let now = Instant::now();
+    let throttle = resp.throttle;
+    resp.next_send_instant = None;
+
+    if let Some(h) = &resp.headers {
+        if now < resp.request_instant + throttle.delay {
+            resp.next_send_instant = Some(resp.request_instant + throttle.delay);
+            return false; // not yet due
+        }
+        match h3_conn.send_response(
+            quic_conn,
+            stream_id,
+            h,
+            false,
+        ) {
+            Ok(_) => {
+                debug!("{} sent response headers ({} B header section) on stream {}", quic_conn.trace_id(), field_section_size(h), stream_id);
+                resp.headers = None;
+            }
+            Err(h3::Error::StreamBlocked) => return false, // try again next time
+            Err(e) => {
+                error!(
+                    "{} error sending response headers ({} B header section) on stream {}: {:?}{}",
+                    quic_conn.trace_id(),
+                    field_section_size(h),
+                    stream_id,
+                    e,
+                    error_hint(&e),
+                );
+                partial_responses.remove(&stream_id);
+                quic_conn.stream_shutdown(stream_id, quiche::Shutdown::Write, h3::WireErrorCode::InternalError as u64).ok();
+                return false;
             }
         }
+    }
 
-        loop {
-            let (buf, fin) = match resp.body {
-                Static(body) => {
-                    (&body[resp.written..], true)
-                }
-                Zeros(len) => {
-                    let remaining = len - resp.written;
-                    if remaining > buf.len() {
-                        (buf, false)
-                    } else {
-                        (&buf[..remaining], true)
-                    }
-                }
-            };
+    let mut max_chunk_len = min(resp.chunk_size.unwrap_or(usize::MAX), buf.len());
+    if throttle.is_throttled() {
+        let body_instant = resp.request_instant + max(throttle.delay, throttle.ttfb);
+        if now < body_instant {
+            resp.next_send_instant = Some(body_instant);
+            return false; // not yet due
+        }
+        let elapsed = now - body_instant;
+        let mut allowed = usize::MAX;
+        if let Some(rate) = throttle.rate {
+            allowed = min(allowed, (rate as f64 * elapsed.as_secs_f64()) as usize + RATE_BURST);
+        }
+        if let Some(interval) = throttle.chunk_interval {
+            let chunks = (elapsed.as_nanos() / interval.as_nanos()) as usize + 1;
+            allowed = min(allowed, chunks.saturating_mul(max_chunk_len));
+        }
+        if resp.written >= allowed
+            && let Some(next) = next_body_send(&throttle, resp.written, resp.body.len(), max_chunk_len) {
+            resp.next_send_instant = Some(resp.request_instant + next);
+            return false; // not yet due
+        }
+        max_chunk_len = min(max_chunk_len, allowed.saturating_sub(resp.written));
+    }
+    if let Some(dgrams) = &mut resp.dgrams {
+        match dgrams.send(quic_conn, stream_id, scratch_buf) {
+            Ok(true) => {}
+            Ok(false) => {
+                resp.next_send_instant = dgrams.next_send_instant();
+                return false;
+            }
+            Err(e) => {
+                error!("{} {} on stream {}", quic_conn.trace_id(), e, stream_id);
+                partial_responses.remove(&stream_id);
+                quic_conn.stream_shutdown(stream_id, quiche::Shutdown::Write, h3::WireErrorCode::InternalError as u64).ok();
+                return false;
+            }
+        }
+        info!("{} sent {} datagrams on stream {}", quic_conn.trace_id(), dgrams.seq, stream_id);
+        resp.trailers = Some(vec![h3::Header::new(b"dgram-sent", dgrams.seq.to_string().as_bytes())]);
+        resp.dgrams = None;
+    }
 
-            let written = match h3_conn.send_body(quic_conn, stream_id, buf, fin) {
-                Ok(v) => v,
-                Err(h3::Error::Done) => continue 'streamLoop,
-                Err(e) => {
-                    partial_responses.remove(&stream_id);
-                    error!("{} stream send failed {:?}", quic_conn.trace_id(), e);
-                    continue 'streamLoop;
+    let body_complete = Some(resp.written) == resp.body.len();
+    if !body_complete || resp.trailers.is_none() {
+        let (buf, fin) = match &resp.body {
+            Owned(body) => {
+                (&body[resp.written..], true)
+            }
+            Generated(len, content) => {
+                let remaining = len - resp.written;
+                let chunk_len = min(remaining, max_chunk_len);
+                let chunk: &[u8] = match content {
+                    Content::Zeros => &buf[..chunk_len],
+                    _ => {
+                        content.fill(resp.written, &mut scratch_buf[..chunk_len]);
+                        &scratch_buf[..chunk_len]
+                    }
+                };
+                (chunk, chunk_len == remaining)
+            }
+            FileBody(file, len) => {
+                let remaining = len - resp.written;
+                let chunk_len = min(remaining, max_chunk_len);
+                let read = match file.read_at(&mut scratch_buf[..chunk_len], resp.written as u64) {
+                    Ok(0) | Err(_) => {
+                        error!("{} failed to read file for stream {}", quic_conn.trace_id(), stream_id);
+                        partial_responses.remove(&stream_id);
+                        quic_conn.stream_shutdown(stream_id, quiche::Shutdown::Write, h3::WireErrorCode::InternalError as u64).ok();
+                        return false;
+                    }
+                    Ok(v) => v,
+                };
+                (&scratch_buf[..read], read == remaining)
+            }
+            Echo(echo) => {
+                if echo.pending.is_empty() && !echo.fin {
+                    return false; // wait for more request data
                 }
-            };
+                let chunk_len = min(echo.pending.len(), max_chunk_len);
+                (&echo.pending[..chunk_len], echo.fin && chunk_len == echo.pending.len())
+            }
+            Tunnel(_) => return false,
+        };
 
-            resp.written += written;
-            if resp.written == resp.body.len() {
+        let written = match h3_conn.send_body(quic_conn, stream_id, buf, fin && resp.trailers.is_none()) {
+            Ok(v) => v,
+            Err(h3::Error::Done) => return false,
+            Err(e) => {
                 partial_responses.remove(&stream_id);
-                continue 'streamLoop;
+                error!("{} stream send failed {:?}", quic_conn.trace_id(), e);
+                return false;
+            }
+        };
+
+        resp.written += written;
+        if let Echo(echo) = &mut resp.body {
+            echo.pending.drain(..written);
+        }
+    }
+
+    if Some(resp.written) == resp.body.len() {
+        if let Some(trailers) = &resp.trailers {
+            match h3_conn.send_additional_headers(quic_conn, stream_id, trailers, true, true) {
+                Ok(_) => {}
+                Err(h3::Error::StreamBlocked) | Err(h3::Error::Done) => return false, // try again next time
+                Err(e) => error!("{} error sending trailers {:?}", quic_conn.trace_id(), e),
             }
         }
+        partial_responses.remove(&stream_id);
+        return false;
+    }
+    true
+}
+
+/// Time after the request at which a throttled response may send more than `written` bytes.
+/// None if the body of `len` bytes is complete, the trailers are not throttled
+fn next_body_send(throttle: &Throttle, written: usize, len: Option<usize>, max_chunk_len: usize) -> Option<Duration> {
+    if len.is_some_and(|len| written >= len) {
+        return None;
+    }
+    let mut next = Duration::ZERO;
+    if let Some(rate) = throttle.rate {
+        // wait until a quarter of the burst may be sent, instead of waking up for every byte
+        let bytes = (written + RATE_BURST / 4).saturating_sub(RATE_BURST);
+        next = max(next, Duration::from_secs_f64(bytes as f64 / rate as f64));
+    }
+    if let Some(interval) = throttle.chunk_interval {
+        let chunks = u32::try_from(written / max_chunk_len).unwrap_or(u32::MAX);
+        next = max(next, interval.saturating_mul(chunks));
     }
+    Some(max(throttle.delay, throttle.ttfb) + next)
 }
 
-fn build_h3_response(request: &[h3::Header], stream_id: u64, quic_conn: &mut quiche::Connection) -> PartialResponse {
+fn build_h3_response(request: &[h3::Header], stream_id: u64, quic_conn: &mut quiche::Connection, roots: &Roots, connect_udp: bool) -> PartialResponse {
     let mut path = None;
+    let mut authority = None;
+    let mut method = None;
+    let mut protocol = None;
+    let mut priority = Priority::default();
 
     for hdr in request {
         match hdr.name() {
@@ -279,74 +748,296 @@ fn build_h3_response(request: &[h3::Header], stream_id: u64, quic_conn: &mut qui
                 path = Some(from_utf8(hdr.value()).unwrap())
             }
             b":method" => {
-                assert_eq!(from_utf8(hdr.value()).unwrap(), "GET")
+                // all methods but CONNECT are answered like GET
+                method = Some(hdr.value());
+            }
+            b":protocol" => {
+                protocol = Some(hdr.value());
             }
             b":scheme" => {
-                assert_eq!(from_utf8(hdr.value()).unwrap(), "https")
+                if hdr.value() != b"https" {
+                    debug!("unexpected scheme {}", String::from_utf8_lossy(hdr.value()));
+                }
             }
             b":authority" => {
-                //TODO
+                authority = from_utf8(hdr.value()).ok();
             }
             b"user-agent" => {
                 //ignore
             }
+            b"priority" => {
+                match from_utf8(hdr.value()).map_err(|e| e.to_string()).and_then(Priority::from_str) {
+                    Ok(p) => priority = p,
+                    Err(e) => debug!("invalid priority header: {}", e),
+                }
+            }
             b => {
                 debug!("{} header not supported", from_utf8(b).unwrap());
             }
         }
     }
 
-    let mem_request = MemRequest::from_str(path.unwrap_or("")).ok();
-
-    const BODY_404: &[u8] = b"404 Not Found; try e.g. /mem/1MB instead";
-    let mem_request = match mem_request {
-        None => return PartialResponse{
-            written: 0,
-            headers: Some(Vec::from([
-                h3::Header::new(b":status", b"404"),
-                h3::Header::new(b"server", b"quiche"),
-                h3::Header::new(b"content-length", BODY_404.len().to_string().as_bytes()),
-            ])),
-            body: Static(BODY_404),
-        },
-        Some(v) => v,
+    let path = path.unwrap_or("");
+    if method == Some(b"CONNECT") {
+        if connect_udp && protocol == Some(CONNECT_UDP_PROTOCOL.as_bytes()) {
+            return build_tunnel_response(path, priority, quic_conn);
+        }
+        let body = b"501 Not Implemented; only CONNECT-UDP is supported, with --connect-udp\n".to_vec();
+        return PartialResponse::new(501, vec![], Owned(body), priority);
+    }
+    if let Some(response) = build_test_response(request, path, stream_id, priority, quic_conn) {
+        return response;
+    }
+    let mem_request = match MemRequest::from_str(path) {
+        Ok(v) => v,
+        Err(MemRequestError::BadRequest(e)) => {
+            let body = format!("400 Bad Request; {}\n\n{}", e, GRAMMAR).into_bytes();
+            return PartialResponse::new(400, vec![], Owned(body), priority);
+        }
+        Err(MemRequestError::NotFound) => {
+            if let Some(root) = roots.get(authority)
+                && let Some((file, len)) = open_file(root, path) {
+                return PartialResponse::new(
+                    200,
+                    vec![h3::Header::new(b"content-type", content_type(path).as_bytes())],
+                    FileBody(file, len),
+                    priority,
+                );
+            }
+            let body = format!("404 Not Found\n\n{}\n{}", ENDPOINTS, GRAMMAR).into_bytes();
+            return PartialResponse::new(404, vec![], Owned(body), priority);
+        }
     };
 
-    PartialResponse {
-        written: 0,
-        headers: Some(Vec::from([
-            h3::Header::new(b":status", b"200"),
-            h3::Header::new(b"server", b"quiche"),
-            h3::Header::new(b"content-length", mem_request.0.to_string().as_bytes()),
-        ])),
-        body: Zeros(mem_request.0),
+    if mem_request.chunk_size.is_some_and(|c| c > H3_BUF_SIZE) {
+        let body = format!("400 Bad Request; chunk size must not exceed {} bytes\n\n{}", H3_BUF_SIZE, GRAMMAR).into_bytes();
+        return PartialResponse::new(400, vec![], Owned(body), priority);
     }
+    let mut response = PartialResponse::new(200, vec![], Generated(mem_request.size, mem_request.content), priority);
+    response.chunk_size = mem_request.chunk_size;
+    response.throttle = mem_request.throttle;
+    response
 }
 
-/// Represents a request path in the form `/mem/<bytes>[<unit>]`;
-/// supported units are none, `B`, `kB`, `MB`, and `GB`
-struct MemRequest(usize);
-
-impl FromStr for MemRequest {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let r = Regex::new(r"/mem/([0-9]+)([a-zA-Z]*)").unwrap();
-        let c = r.captures(s).ok_or(())?;
-        let number = c.get(1).unwrap().as_str().parse::<usize>().map_err(|_| ())?;
-        let unit = c.get(2).unwrap().as_str();
-
-        let number = if unit.is_empty() | unit.eq_ignore_ascii_case("B") {
-            number
-        } else if unit.eq_ignore_ascii_case("kB") {
-            number * 1E3 as usize
-        } else if unit.eq_ignore_ascii_case("MB") {
-            number * 1E6 as usize
-        } else if unit.eq_ignore_ascii_case("GB") {
-            number * 1E9 as usize
-        } else {
-            return Err(());
+/// Open a UDP socket to the target of a CONNECT-UDP request, RFC 9298
+fn build_tunnel_response(path: &str, priority: Priority, quic_conn: &quiche::Connection) -> PartialResponse {
+    let Some((host, port)) = parse_connect_udp_path(path) else {
+        let body = format!("400 Bad Request; expected a path like {}\n", connect_udp_path("<host>", 0)).into_bytes();
+        return PartialResponse::new(400, vec![], Owned(body), priority);
+    };
+    if quic_conn.dgram_max_writable_len().is_none() {
+        let body = b"400 Bad Request; the client does not support datagrams\n".to_vec();
+        return PartialResponse::new(400, vec![], Owned(body), priority);
+    }
+    let socket = (host.as_str(), port).to_socket_addrs()
+        .map_err(|e| e.to_string())
+        .and_then(|mut addrs| addrs.next().ok_or("no address".to_string()))
+        .and_then(|target| {
+            let bind: SocketAddr = match target {
+                SocketAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+                SocketAddr::V6(_) => "[::]:0".parse().unwrap(),
+            };
+            let socket = UdpSocket::bind(bind).map_err(|e| e.to_string())?;
+            socket.connect(target).map_err(|e| e.to_string())?;
+            socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+            Ok(socket)
+        });
+    match socket {
+        Ok(socket) => {
+            info!("{} tunnel to {}:{}", quic_conn.trace_id(), host, port);
+            PartialResponse::new(200, vec![h3::Header::new(b"capsule-protocol", b"?1")], Tunnel(socket), priority)
+        }
+        Err(e) => {
+            let body = format!("502 Bad Gateway; {}:{}: {}\n", host, port, e).into_bytes();
+            PartialResponse::new(502, vec![], Owned(body), priority)
+        }
+    }
+}
+
+/// Forward the UDP payloads of the HTTP datagrams to the tunnel targets and back
+fn handle_tunnels(quic_conn: &mut quiche::Connection, partial_responses: &mut HashMap<u64, PartialResponse>, buf: &mut [u8], wakeup: &Wakeup) {
+    while let Ok(len) = quic_conn.dgram_recv(buf) {
+        let Some((stream_id, payload)) = parse_udp_dgram(&buf[..len]) else {
+            debug!("{} ignored datagram of {} bytes", quic_conn.trace_id(), len);
+            continue;
         };
-        Ok(Self(number))
+        match partial_responses.get(&stream_id) {
+            // losses are fine, like on any UDP path
+            Some(PartialResponse { body: Tunnel(socket), .. }) => { socket.send(payload).ok(); }
+            _ => debug!("{} ignored datagram for unknown tunnel {}", quic_conn.trace_id(), stream_id),
+        }
+    }
+
+    for (stream_id, resp) in partial_responses.iter_mut() {
+        let Tunnel(socket) = &resp.body else {
+            continue;
+        };
+        if resp.watch.is_none() {
+            // the runner only polls its own socket
+            match wakeup.watch(socket) {
+                Ok(watch) => resp.watch = Some(watch),
+                Err(e) => error!("{} failed to watch the socket of tunnel {}: {}", quic_conn.trace_id(), stream_id, e),
+            }
+        }
+        let prefix = udp_dgram_prefix(*stream_id);
+        buf[..prefix.len()].copy_from_slice(&prefix);
+        while let Ok(len) = socket.recv(&mut buf[prefix.len()..]) {
+            if let Err(e) = quic_conn.dgram_send(&buf[..prefix.len() + len]) {
+                debug!("{} dropped datagram of tunnel {}: {:?}", quic_conn.trace_id(), stream_id, e);
+            }
+        }
+    }
+}
+
+/// Build the response of a test endpoint, see [`ENDPOINTS`].
+/// Return None if the path is no test endpoint
+fn build_test_response(request: &[h3::Header], path: &str, stream_id: u64, priority: Priority, quic_conn: &quiche::Connection) -> Option<PartialResponse> {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let response = match path {
+        "/echo" => PartialResponse::new(200, vec![], Echo(EchoBody::default()), priority),
+        "/headers" => {
+            let body = serde_json::to_vec_pretty(&hdrs_to_strings(request)).unwrap();
+            PartialResponse::new(
+                200,
+                vec![h3::Header::new(b"content-type", b"application/json")],
+                Owned(body),
+                priority,
+            )
+        }
+        "/trailers" => {
+            let mut response = PartialResponse::new(200, vec![], Owned(b"trailers follow\n".to_vec()), priority);
+            response.trailers = Some(vec![h3::Header::new(b"x-trailer", b"quiche")]);
+            response
+        }
+        _ if path.starts_with("/dgram/") => {
+            match parse_dgram_request(&path["/dgram/".len()..], query) {
+                Ok(_) if quic_conn.dgram_max_writable_len().is_none() => {
+                    let body = b"400 Bad Request; the client does not support datagrams\n".to_vec();
+                    PartialResponse::new(400, vec![], Owned(body), priority)
+                }
+                Ok(mut dgrams) => {
+                    // H3_DATAGRAM is negotiated along with QUIC datagrams
+                    dgrams.prefix = http_dgram_prefix(stream_id);
+                    let mut response = PartialResponse::new(200, vec![], Owned(vec![]), priority);
+                    response.dgrams = Some(dgrams);
+                    response
+                }
+                Err(e) => {
+                    let body = format!("400 Bad Request; {}\n\n{}", e, ENDPOINTS).into_bytes();
+                    PartialResponse::new(400, vec![], Owned(body), priority)
+                }
+            }
+        }
+        _ if path.starts_with("/hdrs/") => {
+            match parse_hdrs_request(&path["/hdrs/".len()..], query) {
+                Ok(headers) => PartialResponse::new(200, headers, Owned(vec![]), priority),
+                Err(e) => {
+                    let body = format!("400 Bad Request; {}\n\n{}", e, ENDPOINTS).into_bytes();
+                    PartialResponse::new(400, vec![], Owned(body), priority)
+                }
+            }
+        }
+        _ => {
+            let code = path.strip_prefix("/status/")?;
+            match code.parse::<u16>() {
+                // 1xx are no final responses
+                Ok(status @ 200..=599) => {
+                    let body = match status {
+                        204 | 304 => vec![],
+                        _ => format!("{}\n", status).into_bytes(),
+                    };
+                    PartialResponse::new(status, vec![], Owned(body), priority)
+                }
+                _ => {
+                    let body = format!("400 Bad Request; invalid status: {}\n\n{}", code, ENDPOINTS).into_bytes();
+                    PartialResponse::new(400, vec![], Owned(body), priority)
+                }
+            }
+        }
+    };
+    Some(response)
+}
+
+/// Parse the size and options of a `/hdrs/<size>` request to the filler headers
+fn parse_hdrs_request(size: &str, query: &str) -> Result<Vec<h3::Header>, String> {
+    let size = parse_size(size)?;
+    let mut count = None;
+    for option in query.split('&').filter(|o| !o.is_empty()) {
+        match option.split_once('=') {
+            Some(("count", value)) => {
+                count = Some(value.parse::<usize>().map_err(|e| format!("invalid count: {}", e))?);
+            }
+            _ => return Err(format!("unknown option: {}", option)),
+        }
+    }
+    if size > MAX_HDRS_SIZE {
+        return Err(format!("size too large: {} B, at most {} B", size, MAX_HDRS_SIZE));
+    }
+    if count.is_some_and(|c| c > MAX_HDRS_COUNT) {
+        return Err(format!("count too large: at most {}", MAX_HDRS_COUNT));
+    }
+    Ok(filler_headers(size, count))
+}
+
+/// Parse the size and options of a `/dgram/<size>` request
+fn parse_dgram_request(size: &str, query: &str) -> Result<DgramSender, String> {
+    let mut sender = DgramSender::new(parse_size(size)?, None, None);
+    for option in query.split('&').filter(|o| !o.is_empty()) {
+        match option.split_once('=') {
+            Some(("size", value)) => {
+                let size = parse_size(value)?;
+                if size < dgram::HEADER_LEN {
+                    return Err(format!("datagram size must be at least {} bytes", dgram::HEADER_LEN));
+                }
+                sender.size = Some(size);
+            }
+            Some(("rate", value)) => {
+                let rate = parse_rate(value)?;
+                if rate == 0 {
+                    return Err("rate must not be 0".to_string());
+                }
+                sender.rate = Some(rate);
+            }
+            _ => return Err(format!("unknown option: {}", option)),
+        }
+    }
+    Ok(sender)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delayed_body_send() {
+        let throttle = Throttle { delay: Duration::from_millis(200), ttfb: Duration::from_millis(100), ..Throttle::default() };
+        assert_eq!(next_body_send(&throttle, 0, Some(1000), 1000), Some(Duration::from_millis(200)));
+        let throttle = Throttle { ttfb: Duration::from_millis(300), ..throttle };
+        assert_eq!(next_body_send(&throttle, 0, Some(1000), 1000), Some(Duration::from_millis(300)));
+    }
+
+    #[test]
+    fn rate_limited_body_send() {
+        let throttle = Throttle { rate: Some(4096), ..Throttle::default() };
+        // the burst may be sent right away
+        assert_eq!(next_body_send(&throttle, 0, Some(100_000), 1000), Some(Duration::ZERO));
+        // then wait until a quarter of the burst is allowed again
+        assert_eq!(next_body_send(&throttle, RATE_BURST, Some(100_000), 1000), Some(Duration::from_secs(1)));
+        assert_eq!(next_body_send(&throttle, 2 * RATE_BURST, None, 1000), Some(Duration::from_secs(5)));
+        let throttle = Throttle { delay: Duration::from_millis(200), ..throttle };
+        assert_eq!(next_body_send(&throttle, RATE_BURST, Some(100_000), 1000), Some(Duration::from_millis(1200)));
+    }
+
+    #[test]
+    fn chunk_interval_body_send() {
+        let throttle = Throttle { chunk_interval: Some(Duration::from_millis(10)), ..Throttle::default() };
+        assert_eq!(next_body_send(&throttle, 3000, Some(100_000), 1000), Some(Duration::from_millis(30)));
+        assert_eq!(next_body_send(&throttle, 3500, Some(100_000), 1000), Some(Duration::from_millis(30)));
+    }
+
+    #[test]
+    fn finished_body_send() {
+        let throttle = Throttle { rate: Some(4096), chunk_interval: Some(Duration::from_millis(10)), ..Throttle::default() };
+        assert_eq!(next_body_send(&throttle, 100_000, Some(100_000), 1000), None);
     }
 }
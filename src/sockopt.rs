@@ -0,0 +1,88 @@
+use crate::args::SocketArgs;
+use log::warn;
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::socket::{getsockopt, setsockopt, sockopt};
+use std::fmt;
+use std::net::SocketAddr;
+use std::os::fd::{AsFd, AsRawFd};
+
+/// The effective options of a UDP socket
+#[derive(Clone, Copy, Debug)]
+pub struct SocketInfo {
+    pub sndbuf: usize,
+    pub rcvbuf: usize,
+    /// DSCP of the sent packets, if set
+    pub dscp: Option<u8>,
+}
+
+impl fmt::Display for SocketInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sndbuf {} B, rcvbuf {} B", self.sndbuf, self.rcvbuf)?;
+        match self.dscp {
+            Some(dscp) => write!(f, ", dscp {}", dscp),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Apply the socket options and return the effective values.
+/// Warns if the kernel clamps a buffer size
+pub fn configure_socket(fd: &impl AsFd, local_addr: SocketAddr, args: &SocketArgs) -> SocketInfo {
+    if let Some(size) = args.sndbuf {
+        setsockopt(fd, sockopt::SndBuf, &size).unwrap_or_else(|e| panic!("failed to set send buffer size: {}", e));
+    }
+    if let Some(size) = args.rcvbuf {
+        setsockopt(fd, sockopt::RcvBuf, &size).unwrap_or_else(|e| panic!("failed to set receive buffer size: {}", e));
+    }
+    let dscp = args.dscp.map(|dscp| {
+        // nix wraps IP_TOS and IPV6_TCLASS on Linux only
+        let (level, name) = match local_addr {
+            SocketAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_TOS),
+            SocketAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_TCLASS),
+        };
+        // the DSCP is the upper 6 bits of the traffic class, the lower 2 bits are the ECN field
+        set_int_option(fd, level, name, (dscp as libc::c_int) << 2)
+            .unwrap_or_else(|e| panic!("failed to set DSCP: {}", e));
+        let tos = get_int_option(fd, level, name).unwrap_or_else(|e| panic!("failed to get DSCP: {}", e));
+        (tos >> 2) as u8
+    });
+    let mut sndbuf = getsockopt(fd, sockopt::SndBuf).unwrap();
+    let mut rcvbuf = getsockopt(fd, sockopt::RcvBuf).unwrap();
+    if cfg!(target_os = "linux") {
+        // Linux reports twice the requested size, to account for its bookkeeping overhead
+        sndbuf /= 2;
+        rcvbuf /= 2;
+    }
+    if args.sndbuf.is_some_and(|size| sndbuf < size) {
+        warn!("send buffer clamped to {} B by the kernel, raise net.core.wmem_max", sndbuf);
+    }
+    if args.rcvbuf.is_some_and(|size| rcvbuf < size) {
+        warn!("receive buffer clamped to {} B by the kernel, raise net.core.rmem_max", rcvbuf);
+    }
+    SocketInfo { sndbuf, rcvbuf, dscp }
+}
+
+fn set_int_option(fd: &impl AsFd, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> nix::Result<()> {
+    // SAFETY: the value outlives the call and the length matches its type
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_fd().as_raw_fd(),
+            level,
+            name,
+            (&raw const value).cast(),
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    Errno::result(ret).map(drop)
+}
+
+fn get_int_option(fd: &impl AsFd, level: libc::c_int, name: libc::c_int) -> nix::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: the value and length outlive the call and the length matches the value's type
+    let ret = unsafe {
+        libc::getsockopt(fd.as_fd().as_raw_fd(), level, name, (&raw mut value).cast(), &raw mut len)
+    };
+    Errno::result(ret).map(|_| value)
+}
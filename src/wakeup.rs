@@ -0,0 +1,137 @@
+use log::debug;
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token, Waker};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+const WAKER: Token = Token(0);
+
+enum Command {
+    At(Instant),
+    Watch(usize, UdpSocket),
+    Unwatch(usize),
+    Stop,
+}
+
+/// Wakes the runner at deadlines and when watched sockets become readable.
+/// The runner only polls its QUIC socket and the connection timeouts,
+/// so a helper thread sends a one byte datagram to the QUIC socket, which the endpoint drops as invalid.
+/// This keeps timers off the wire, unlike eliciting ACKs from the peer
+pub struct Wakeup {
+    commands: Sender<Command>,
+    waker: Arc<Waker>,
+    next_watch_id: AtomicUsize,
+    /// the earliest pending deadline
+    scheduled: Option<Instant>,
+}
+
+impl Wakeup {
+    /// Start the helper thread for the runner listening on `runner_addr`
+    pub fn new(runner_addr: SocketAddr) -> Self {
+        let poll = Poll::new().unwrap();
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER).unwrap());
+        let (commands, rx) = channel();
+        let target = match runner_addr {
+            SocketAddr::V4(a) if a.ip().is_unspecified() => SocketAddr::from((Ipv4Addr::LOCALHOST, a.port())),
+            SocketAddr::V6(a) if a.ip().is_unspecified() => SocketAddr::from((Ipv6Addr::LOCALHOST, a.port())),
+            a => a,
+        };
+        thread::spawn(move || run(poll, rx, target));
+        Self { commands, waker, next_watch_id: AtomicUsize::new(1), scheduled: None }
+    }
+
+    /// Wake the runner at `deadline`, unless it is woken before anyway
+    pub fn at(&mut self, deadline: Instant) {
+        if self.scheduled.is_some_and(|s| s > Instant::now() && s <= deadline) {
+            return;
+        }
+        self.scheduled = Some(deadline);
+        send(&self.commands, &self.waker, Command::At(deadline));
+    }
+
+    /// Wake the runner whenever data arrives on `socket`, until the returned guard is dropped
+    pub fn watch(&self, socket: &UdpSocket) -> io::Result<Watch> {
+        let id = self.next_watch_id.fetch_add(1, Ordering::Relaxed);
+        send(&self.commands, &self.waker, Command::Watch(id, socket.try_clone()?));
+        Ok(Watch { id, commands: self.commands.clone(), waker: self.waker.clone() })
+    }
+}
+
+impl Drop for Wakeup {
+    fn drop(&mut self) {
+        send(&self.commands, &self.waker, Command::Stop);
+    }
+}
+
+/// Stops watching a socket when dropped
+pub struct Watch {
+    id: usize,
+    commands: Sender<Command>,
+    waker: Arc<Waker>,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        send(&self.commands, &self.waker, Command::Unwatch(self.id));
+    }
+}
+
+fn send(commands: &Sender<Command>, waker: &Waker, command: Command) {
+    if commands.send(command).is_ok() {
+        waker.wake().ok();
+    }
+}
+
+fn run(mut poll: Poll, commands: Receiver<Command>, target: SocketAddr) {
+    let bind: SocketAddr = match target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).unwrap();
+    let mut deadlines = BinaryHeap::new();
+    let mut watched = HashMap::new();
+    let mut events = Events::with_capacity(64);
+    loop {
+        let timeout = deadlines.peek().map(|Reverse(d): &Reverse<Instant>| d.saturating_duration_since(Instant::now()));
+        if let Err(e) = poll.poll(&mut events, timeout) {
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            panic!("failed to poll: {}", e);
+        }
+        let mut wake = events.iter().any(|e| e.token() != WAKER);
+        loop {
+            match commands.try_recv() {
+                Ok(Command::At(deadline)) => deadlines.push(Reverse(deadline)),
+                Ok(Command::Watch(id, s)) => {
+                    // edge triggered, so each arriving datagram is an event although the runner reads the socket
+                    poll.registry().register(&mut SourceFd(&s.as_raw_fd()), Token(id), Interest::READABLE).unwrap();
+                    watched.insert(id, s);
+                }
+                Ok(Command::Unwatch(id)) => {
+                    if let Some(s) = watched.remove(&id) {
+                        poll.registry().deregister(&mut SourceFd(&s.as_raw_fd())).ok();
+                    }
+                }
+                Ok(Command::Stop) | Err(TryRecvError::Disconnected) => return,
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        let now = Instant::now();
+        while deadlines.peek().is_some_and(|Reverse(d)| *d <= now) {
+            deadlines.pop();
+            wake = true;
+        }
+        if wake && let Err(e) = socket.send_to(&[0], target) {
+            debug!("failed to wake the runner: {}", e);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::priority::Priority;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A single request of a workload
+#[derive(Clone, Debug)]
+pub struct WorkloadEntry {
+    /// Index of the connection to send the request on
+    pub conn: usize,
+    pub method: String,
+    pub path: String,
+    /// Number of request body bytes to send
+    pub body_size: usize,
+    /// Time after the client started, before the request is sent
+    pub start_offset: Duration,
+    /// Index of the entry that has to complete before the request is sent
+    pub depends_on: Option<usize>,
+    /// Sent as `priority` header if some
+    pub priority: Option<Priority>,
+}
+
+/// A list of requests, e.g. parsed from a workload file.
+///
+/// Each line describes one request in the form
+/// `<conn> <method> <path> [body=<bytes>] [start=<ms>] [after=<entry>] [priority=<u=<urgency>[,i]>]`.
+/// Entries are numbered from 0 in the order of the file.
+/// Empty lines and lines starting with `#` are ignored.
+///
+/// Example:
+/// ```text
+/// # bulk download next to many small objects
+/// 0 GET /mem/1GB
+/// 0 GET /mem/10kB start=100 priority=u=1,i
+/// 1 POST /mem/0 body=1000000
+/// 1 GET /mem/1MB after=1
+/// ```
+#[derive(Clone, Debug)]
+pub struct Workload {
+    pub entries: Vec<WorkloadEntry>,
+}
+
+impl Workload {
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let s = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::from_str(&s)
+    }
+
+    /// Number of connections required by this workload
+    pub fn num_conns(&self) -> usize {
+        self.entries.iter().map(|e| e.conn + 1).max().unwrap_or(0)
+    }
+}
+
+impl FromStr for Workload {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = WorkloadEntry::from_str(line)
+                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+            entries.push(entry);
+        }
+        if entries.is_empty() {
+            return Err("no requests".to_string());
+        }
+        for (i, e) in entries.iter().enumerate() {
+            if e.depends_on.is_some_and(|d| d >= i) {
+                return Err(format!("entry {} can only depend on an earlier entry", i));
+            }
+        }
+        Ok(Self { entries })
+    }
+}
+
+impl FromStr for WorkloadEntry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let conn = fields.next()
+            .ok_or("missing connection")?
+            .parse::<usize>()
+            .map_err(|e| format!("invalid connection: {}", e))?;
+        let method = fields.next().ok_or("missing method")?.to_string();
+        let path = fields.next().ok_or("missing path")?.to_string();
+        if !path.starts_with('/') {
+            return Err(format!("path must start with '/': {}", path));
+        }
+        let mut entry = WorkloadEntry {
+            conn,
+            method,
+            path,
+            body_size: 0,
+            start_offset: Duration::ZERO,
+            depends_on: None,
+            priority: None,
+        };
+        for option in fields {
+            let (key, value) = option.split_once('=')
+                .ok_or_else(|| format!("expected <key>=<value>: {}", option))?;
+            match key {
+                "body" => {
+                    entry.body_size = value.parse()
+                        .map_err(|e| format!("invalid body size: {}", e))?;
+                }
+                "start" => {
+                    entry.start_offset = Duration::from_millis(value.parse()
+                        .map_err(|e| format!("invalid start offset: {}", e))?);
+                }
+                "after" => {
+                    entry.depends_on = Some(value.parse()
+                        .map_err(|e| format!("invalid entry: {}", e))?);
+                }
+                "priority" => {
+                    entry.priority = Some(value.parse()?);
+                }
+                _ => return Err(format!("unknown option: {}", key)),
+            }
+        }
+        Ok(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_entries() {
+        let workload = Workload::from_str("\
+# comment
+
+0 GET /mem/1GB
+1 POST /mem/0 body=1000 start=250 after=0 priority=u=1,i
+").unwrap();
+        assert_eq!(workload.entries.len(), 2);
+        assert_eq!(workload.num_conns(), 2);
+        let e = &workload.entries[1];
+        assert_eq!((e.conn, e.method.as_str(), e.path.as_str()), (1, "POST", "/mem/0"));
+        assert_eq!(e.body_size, 1000);
+        assert_eq!(e.start_offset, Duration::from_millis(250));
+        assert_eq!(e.depends_on, Some(0));
+        assert_eq!(e.priority, Some(Priority { urgency: 1, incremental: true }));
+    }
+
+    #[test]
+    fn reject_malformed_lines() {
+        for (s, e) in [
+            ("", "no requests"),
+            ("0 GET", "line 1: missing path"),
+            ("x GET /", "line 1: invalid connection"),
+            ("0 GET mem/1", "line 1: path must start with '/'"),
+            ("0 GET / body", "line 1: expected <key>=<value>"),
+            ("0 GET / body=-1", "line 1: invalid body size"),
+            ("0 GET / start=1.5", "line 1: invalid start offset"),
+            ("0 GET /\n0 GET / foo=1", "line 2: unknown option: foo"),
+        ] {
+            let err = Workload::from_str(s).unwrap_err();
+            assert!(err.starts_with(e), "{:?}: {}", s, err);
+        }
+    }
+
+    #[test]
+    fn depend_on_earlier_entries_only() {
+        assert!(Workload::from_str("0 GET / after=0").is_err());
+        assert!(Workload::from_str("0 GET /\n0 GET / after=2\n0 GET /").is_err());
+        assert!(Workload::from_str("0 GET /\n0 GET / after=0").is_ok());
+    }
+}
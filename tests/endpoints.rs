@@ -0,0 +1,481 @@
+use quiche_mio_runner::quiche_endpoint::quiche::PROTOCOL_VERSION;
+use quiche_perf::args::{CertGenArgs, ClientArgs, GenCertArgs, H3Args, KeyType, Protocol, ServerArgs, SocketArgs};
+use quiche_perf::cert::gen_cert;
+use quiche_perf::client::{client, AppData};
+use quiche_perf::server::server;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Run a server on `port` and a client with the modified default args against it
+fn run(port: u16, path: &str, modify: impl FnOnce(&mut ClientArgs)) -> AppData {
+    run_with_server(port, path, |_| {}, modify)
+}
+
+/// Like [`run`], with modified default server args
+fn run_with_server(port: u16, path: &str, modify_server: impl FnOnce(&mut ServerArgs), modify: impl FnOnce(&mut ClientArgs)) -> AppData {
+    run_repeated(port, path, modify_server, 1, modify).pop().unwrap()
+}
+
+/// Like [`run_with_server`], running the client `times` times in a row against the same server
+fn run_repeated(port: u16, path: &str, modify_server: impl FnOnce(&mut ServerArgs), times: usize, modify: impl FnOnce(&mut ClientArgs)) -> Vec<AppData> {
+    let (mut close_pipe_tx, mut close_pipe_rx) = mio::unix::pipe::new().unwrap();
+    let bind = format!("127.0.0.1:{}", port).parse().unwrap();
+    let mut server_args = ServerArgs {
+        cert: vec![],
+        key: vec![],
+        cert_gen: CertGenArgs::default(),
+        write_generated_cert: None,
+        client_ca: None,
+        require_client_cert: false,
+        max_udp_payload: 1500-44,
+        disable_gro: true,
+        disable_gso: true,
+        socket: SocketArgs::default(),
+        bind,
+        max_streams_bidi: 100,
+        max_streams_uni: 100,
+        idle_timeout: 1000,
+        root: None,
+        vhost: vec![],
+        connect_udp: false,
+        protocol: Protocol::H3,
+        quic_version: PROTOCOL_VERSION,
+        groups: None,
+        early_data: false,
+        h3: H3Args::default(),
+    };
+    modify_server(&mut server_args);
+    let server_join_handle = thread::spawn(move || {
+        server(&server_args, Some(&mut close_pipe_rx));
+    });
+    let mut args = ClientArgs {
+        url: format!("https://127.0.0.1:{}{}", port, path),
+        addr: None,
+        no_verify: true,
+        max_udp_payload: 1500-44,
+        disable_gro: true,
+        disable_gso: true,
+        socket: SocketArgs::default(),
+        cert: None,
+        pin_spki: vec![],
+        client_cert: None,
+        client_key: None,
+        streams: 1,
+        method: "GET".to_string(),
+        headers: vec![],
+        header_size: None,
+        header_count: None,
+        user_agent: "quiche".to_string(),
+        priority: None,
+        verify: false,
+        output: None,
+        output_dir: None,
+        print_headers: false,
+        workload: None,
+        har: None,
+        connect_udp: None,
+        udp_total: 10_000_000,
+        udp_size: 1200,
+        udp_rate: None,
+        compare_direct: false,
+        protocol: Protocol::H3,
+        quic_version: PROTOCOL_VERSION,
+        groups: None,
+        session_file: None,
+        early_data: false,
+        silent_close: true,
+        idle_timeout: 1000,
+        h3: H3Args::default(),
+    };
+    modify(&mut args);
+    let app_data = (0..times).map(|_| client(&args)).collect();
+    close_pipe_tx.write_all(&[0]).unwrap();
+    server_join_handle.join().unwrap();
+    app_data
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("quiche_perf_{}_{}", std::process::id(), name))
+}
+
+#[test]
+fn echo() {
+    let workload = temp_path("echo_workload");
+    fs::write(&workload, "0 POST /echo body=1000000\n0 GET /echo\n").unwrap();
+    let app_data = run(4440, "/", |args| args.workload = Some(workload.clone()));
+    fs::remove_file(&workload).unwrap();
+    let mut results = app_data.results;
+    results.sort_by_key(|r| r.entry);
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].status, Some(200));
+    assert_eq!(results[0].received_body_bytes, 1_000_000);
+    assert_eq!(results[1].received_body_bytes, 0);
+}
+
+#[test]
+fn headers() {
+    let output = temp_path("headers_output");
+    let app_data = run(4441, "/headers", |args| {
+        args.headers = vec!["x-test: 42".parse().unwrap(), "user-agent: curl".parse().unwrap()];
+        args.output = Some(output.clone());
+    });
+    let body = fs::read_to_string(&output).unwrap();
+    fs::remove_file(&output).unwrap();
+    assert_eq!(app_data.results[0].status, Some(200));
+    let headers: Vec<(String, String)> = serde_json::from_str(&body).unwrap();
+    assert!(headers.contains(&(":path".to_string(), "/headers".to_string())));
+    assert!(headers.contains(&("x-test".to_string(), "42".to_string())));
+    let user_agents = headers.iter().filter(|(name, _)| name == "user-agent").collect::<Vec<_>>();
+    assert_eq!(user_agents, [&("user-agent".to_string(), "curl".to_string())]);
+}
+
+#[test]
+fn status() {
+    let workload = temp_path("status_workload");
+    fs::write(&workload, "0 GET /status/418\n0 GET /status/204\n0 GET /status/99\n").unwrap();
+    let app_data = run(4442, "/", |args| args.workload = Some(workload.clone()));
+    fs::remove_file(&workload).unwrap();
+    let mut results = app_data.results;
+    results.sort_by_key(|r| r.entry);
+    assert_eq!(results.iter().map(|r| r.status).collect::<Vec<_>>(), [Some(418), Some(204), Some(400)]);
+    assert_eq!(results[1].received_body_bytes, 0);
+}
+
+#[test]
+fn trailers() {
+    let app_data = run(4443, "/trailers", |_| {});
+    let result = &app_data.results[0];
+    assert_eq!(result.status, Some(200));
+    assert!(result.received_body_bytes > 0);
+    assert_eq!(result.trailers, [("x-trailer".to_string(), "quiche".to_string())]);
+}
+
+#[test]
+fn large_request_headers() {
+    let output = temp_path("large_request_headers_output");
+    let app_data = run(4444, "/headers", |args| {
+        args.header_size = Some(64_000);
+        args.header_count = Some(64);
+        args.output = Some(output.clone());
+    });
+    let body = fs::read_to_string(&output).unwrap();
+    fs::remove_file(&output).unwrap();
+    assert_eq!(app_data.results[0].status, Some(200));
+    let headers: Vec<(String, String)> = serde_json::from_str(&body).unwrap();
+    let filler = headers.iter().filter(|(name, _)| name.starts_with("x-filler-")).collect::<Vec<_>>();
+    assert_eq!(filler.len(), 64);
+    assert_eq!(filler.iter().map(|(name, value)| name.len() + value.len()).sum::<usize>(), 64_000);
+}
+
+#[test]
+fn large_response_headers() {
+    let app_data = run(4445, "/hdrs/64KiB?count=1000", |_| {});
+    assert_eq!(app_data.results[0].status, Some(200));
+}
+
+#[test]
+fn response_headers_too_large() {
+    let workload = temp_path("response_headers_too_large_workload");
+    fs::write(&workload, "0 GET /hdrs/2MiB\n0 GET /hdrs/1KiB?count=100000\n").unwrap();
+    let app_data = run(4460, "/", |args| args.workload = Some(workload.clone()));
+    fs::remove_file(&workload).unwrap();
+    assert_eq!(app_data.results.iter().map(|r| r.status).collect::<Vec<_>>(), [Some(400), Some(400)]);
+}
+
+#[test]
+fn response_headers_exceed_max_field_section_size() {
+    let app_data = run(4446, "/hdrs/64KiB", |args| {
+        args.h3.max_field_section_size = Some(16_384);
+    });
+    assert!(app_data.results.is_empty());
+}
+
+#[test]
+fn dgram() {
+    let app_data = run(4447, "/dgram/1MB?size=1000", |_| {});
+    let result = &app_data.results[0];
+    assert_eq!(result.status, Some(200));
+    let stats = result.dgram.as_ref().unwrap();
+    assert_eq!(stats.sent, Some(1000));
+    // no loss on loopback, the send queue is only refilled as it drains
+    assert_eq!(stats.received, 1000);
+    assert_eq!(stats.lost(), Some(0));
+    assert!(stats.goodput().is_finite() && stats.goodput() > 0.0);
+    assert!(stats.jitter < Duration::from_secs(1));
+}
+
+#[test]
+fn connect_udp() {
+    let target = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let target_addr = target.local_addr().unwrap();
+    // echo target, left running until the test process exits
+    thread::spawn(move || {
+        let mut buf = [0; 65_536];
+        while let Ok((len, peer)) = target.recv_from(&mut buf) {
+            target.send_to(&buf[..len], peer).ok();
+        }
+    });
+    let app_data = run_with_server(4448, "/", |server_args| server_args.connect_udp = true, |args| {
+        args.connect_udp = Some(target_addr.to_string());
+        args.udp_total = 1_200_000;
+        args.udp_rate = Some(100_000_000 / 8);
+    });
+    let result = &app_data.results[0];
+    assert_eq!(result.status, Some(200));
+    let stats = result.dgram.as_ref().unwrap();
+    assert_eq!(stats.sent, Some(1000));
+    assert!(stats.received > 0);
+}
+
+#[test]
+fn perf_protocol() {
+    let workload = temp_path("perf_workload");
+    fs::write(&workload, "0 GET /mem/10MB\n0 GET /mem/0 body=1000000\n").unwrap();
+    let app_data = run_with_server(4449, "/", |server_args| server_args.protocol = Protocol::Perf, |args| {
+        args.protocol = Protocol::Perf;
+        args.workload = Some(workload.clone());
+    });
+    fs::remove_file(&workload).unwrap();
+    let mut results = app_data.results;
+    results.sort_by_key(|r| r.entry);
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].received_body_bytes, 10_000_000);
+    assert_eq!(results[1].received_body_bytes, 0);
+}
+
+#[test]
+fn hq_interop() {
+    let root = temp_path("hq_root");
+    let downloads = temp_path("hq_downloads");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("file.txt"), "hello hq-interop\n").unwrap();
+    let workload = temp_path("hq_workload");
+    fs::write(&workload, "0 GET /file.txt\n0 GET /mem/1MB\n").unwrap();
+    let app_data = run_with_server(4450, "/", |server_args| {
+        server_args.protocol = Protocol::Hq;
+        server_args.root = Some(root.clone());
+    }, |args| {
+        args.protocol = Protocol::Hq;
+        args.workload = Some(workload.clone());
+        args.output_dir = Some(downloads.clone());
+    });
+    let file = fs::read_to_string(downloads.join("file.txt")).unwrap();
+    let mem = fs::read(downloads.join("1MB")).unwrap();
+    fs::remove_file(&workload).unwrap();
+    fs::remove_dir_all(&root).unwrap();
+    fs::remove_dir_all(&downloads).unwrap();
+    assert_eq!(app_data.results.len(), 2);
+    assert_eq!(file, "hello hq-interop\n");
+    assert_eq!(mem.len(), 1_000_000);
+}
+
+#[test]
+fn version_negotiation() {
+    // a reserved version the server has to answer with a Version Negotiation packet
+    let app_data = run(4451, "/mem/1kB", |args| args.quic_version = 0x1a2a_3a4a);
+    assert_eq!(app_data.results[0].status, Some(200));
+    assert_eq!(app_data.results[0].quic_version, PROTOCOL_VERSION);
+}
+
+#[test]
+fn tls_groups() {
+    let app_data = run_with_server(4452, "/mem/1kB", |server_args| {
+        server_args.groups = Some("P-256:X25519".to_string());
+    }, |args| args.groups = Some("P-256".to_string()));
+    assert_eq!(app_data.results[0].status, Some(200));
+    assert_eq!(app_data.results[0].tls.group.as_deref(), Some("P-256"));
+    assert!(app_data.results[0].tls.cipher.is_some());
+}
+
+#[test]
+fn resumption_with_early_data() {
+    let session_file = temp_path("resumption_session");
+    let app_data = run_repeated(4461, "/mem/1kB", |server_args| server_args.early_data = true, 2, |args| {
+        args.session_file = Some(session_file.clone());
+        args.early_data = true;
+    });
+    fs::remove_file(&session_file).unwrap();
+    assert!(!app_data[0].results[0].resumed);
+    assert!(app_data[1].results[0].resumed);
+    assert!(!app_data[0].results[0].tls.early_data);
+    assert!(app_data[1].results[0].tls.early_data);
+    assert_eq!(app_data[1].results[0].status, Some(200));
+}
+
+#[test]
+fn spki_pin_mismatch() {
+    let app_data = run(4453, "/mem/1kB", |args| {
+        args.no_verify = false;
+        args.pin_spki = vec!["AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string()];
+    });
+    assert!(app_data.results.is_empty());
+    assert_eq!(app_data.tls_alerts, ["bad_certificate"]);
+}
+
+#[test]
+fn generated_cert_is_trusted() {
+    let dir = temp_path("generated_cert");
+    gen_cert(&GenCertArgs {
+        cert_gen: CertGenArgs { key_type: KeyType::Ed25519, ..CertGenArgs::default() },
+        dir: dir.clone(),
+    });
+    let app_data = run_with_server(4454, "/mem/1kB", |server_args| {
+        server_args.cert = vec![dir.join("cert.pem")];
+        server_args.key = vec![dir.join("key.pem")];
+    }, |args| {
+        args.no_verify = false;
+        args.cert = Some(dir.join("cert.pem"));
+    });
+    let spki = fs::read_to_string(dir.join("spki")).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(app_data.results[0].status, Some(200));
+    assert!(!spki.trim().is_empty());
+}
+
+#[test]
+fn client_cert() {
+    let client_dir = temp_path("client_cert");
+    gen_cert(&GenCertArgs { cert_gen: CertGenArgs::default(), dir: client_dir.clone() });
+    let require_client_cert = |server_args: &mut ServerArgs| {
+        server_args.client_ca = Some(client_dir.join("cert.pem"));
+        server_args.require_client_cert = true;
+    };
+    let with_cert = run_with_server(4455, "/mem/1kB", require_client_cert, |args| {
+        args.client_cert = Some(client_dir.join("cert.pem"));
+        args.client_key = Some(client_dir.join("key.pem"));
+    });
+    let without_cert = run_with_server(4456, "/mem/1kB", require_client_cert, |_| {});
+    fs::remove_dir_all(&client_dir).unwrap();
+    assert_eq!(with_cert.results[0].status, Some(200));
+    assert!(without_cert.results.is_empty());
+    assert_eq!(without_cert.tls_alerts, ["certificate_required"]);
+}
+
+#[test]
+fn sni_and_vhost() {
+    let a = temp_path("sni_a");
+    let b = temp_path("sni_b");
+    let www = temp_path("sni_www");
+    gen_cert(&GenCertArgs { cert_gen: CertGenArgs { cert_san: vec!["a.test".to_string()], ..CertGenArgs::default() }, dir: a.clone() });
+    gen_cert(&GenCertArgs { cert_gen: CertGenArgs { cert_san: vec!["b.test".to_string()], ..CertGenArgs::default() }, dir: b.clone() });
+    fs::create_dir_all(&www).unwrap();
+    fs::write(www.join("index.html"), "b.test\n").unwrap();
+    let output = temp_path("sni_output");
+    let app_data = run_with_server(4457, "/index.html", |server_args| {
+        server_args.cert = vec![a.join("cert.pem"), b.join("cert.pem")];
+        server_args.key = vec![a.join("key.pem"), b.join("key.pem")];
+        server_args.vhost = vec![format!("b.test={}", www.display()).parse().unwrap()];
+    }, |args| {
+        // verified against the certificate of b.test only
+        args.url = "https://b.test:4457/index.html".to_string();
+        args.addr = Some("127.0.0.1:4457".parse().unwrap());
+        args.no_verify = false;
+        args.cert = Some(b.join("cert.pem"));
+        args.output = Some(output.clone());
+    });
+    let body = fs::read_to_string(&output).unwrap();
+    for dir in [&a, &b, &www] {
+        fs::remove_dir_all(dir).unwrap();
+    }
+    fs::remove_file(&output).unwrap();
+    assert_eq!(app_data.results[0].status, Some(200));
+    assert_eq!(body, "b.test\n");
+}
+
+#[test]
+fn throttled_response() {
+    let start = Instant::now();
+    // 100 kB/s after the first 16 KiB
+    let app_data = run(4464, "/mem/100kB?delay=200ms&rate=800kbps", |_| {});
+    let result = &app_data.results[0];
+    assert_eq!(result.status, Some(200));
+    assert_eq!(result.received_body_bytes, 100_000);
+    assert!(result.received_body_instant - result.received_header_instant >= Duration::from_millis(800));
+    assert!(start.elapsed() >= Duration::from_millis(1000));
+}
+
+#[test]
+fn urgent_response_first() {
+    let workload = temp_path("urgent_response_first_workload");
+    fs::write(&workload, "0 GET /mem/10MB priority=u=7\n0 GET /mem/100kB priority=u=0\n").unwrap();
+    let app_data = run(4463, "/", |args| args.workload = Some(workload.clone()));
+    fs::remove_file(&workload).unwrap();
+    let mut results = app_data.results;
+    results.sort_by_key(|r| r.entry);
+    assert_eq!(results.iter().map(|r| r.received_body_bytes).collect::<Vec<_>>(), [10_000_000, 100_000]);
+    // the less urgent request was sent first
+    assert!(results[1].received_body_instant < results[0].received_body_instant);
+}
+
+#[test]
+fn path_traversal() {
+    let root = temp_path("traversal_root");
+    let secret = temp_path("traversal_secret.txt");
+    fs::create_dir_all(root.join("dir")).unwrap();
+    fs::write(root.join("dir/file.txt"), "file\n").unwrap();
+    fs::write(&secret, "secret\n").unwrap();
+    let name = secret.file_name().unwrap().to_str().unwrap();
+    let workload = temp_path("traversal_workload");
+    fs::write(&workload, format!(
+        "0 GET /dir/file.txt\n0 GET /../{name}\n0 GET /%2e%2e/{name}\n0 GET /dir%2F..%2F..%2F{name}\n"
+    )).unwrap();
+    let app_data = run_with_server(4462, "/", |server_args| server_args.root = Some(root.clone()), |args| {
+        args.workload = Some(workload.clone());
+    });
+    fs::remove_dir_all(&root).unwrap();
+    fs::remove_file(&secret).unwrap();
+    fs::remove_file(&workload).unwrap();
+    let mut results = app_data.results;
+    results.sort_by_key(|r| r.entry);
+    assert_eq!(results.iter().map(|r| r.status).collect::<Vec<_>>(), [Some(200), Some(404), Some(404), Some(404)]);
+}
+
+#[test]
+fn generated_key_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = temp_path("generated_key_is_private");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("key.pem"), "").unwrap();
+    fs::set_permissions(dir.join("key.pem"), fs::Permissions::from_mode(0o644)).unwrap();
+    gen_cert(&GenCertArgs { cert_gen: CertGenArgs::default(), dir: dir.clone() });
+    let mode = fs::metadata(dir.join("key.pem")).unwrap().permissions().mode();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn cert_reload() {
+    let dir = temp_path("cert_reload");
+    gen_cert(&GenCertArgs { cert_gen: CertGenArgs::default(), dir: dir.clone() });
+    let app_data = run_with_server(4458, "/mem/1kB", |server_args| {
+        server_args.cert = vec![dir.join("cert.pem")];
+        server_args.key = vec![dir.join("key.pem")];
+    }, |args| {
+        // replace the certificate once the server runs with the first one, and wait for the next check
+        thread::sleep(Duration::from_millis(1100));
+        gen_cert(&GenCertArgs { cert_gen: CertGenArgs::default(), dir: dir.clone() });
+        args.pin_spki = vec![fs::read_to_string(dir.join("spki")).unwrap().trim().to_string()];
+    });
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(app_data.results[0].status, Some(200));
+}
+
+#[test]
+fn socket_options() {
+    let app_data = run_with_server(4459, "/mem/1MB", |server_args| {
+        server_args.socket.dscp = Some(46);
+    }, |args| {
+        // below the default limits of the kernel, so they are not clamped
+        args.socket.rcvbuf = Some(100_000);
+        args.socket.sndbuf = Some(100_000);
+        args.socket.dscp = Some(10);
+    });
+    assert_eq!(app_data.results[0].status, Some(200));
+    assert_eq!(app_data.results[0].received_body_bytes, 1_000_000);
+    assert_eq!(app_data.socket_info.rcvbuf, 100_000);
+    assert_eq!(app_data.socket_info.sndbuf, 100_000);
+    assert_eq!(app_data.socket_info.dscp, Some(10));
+}